@@ -16,14 +16,116 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use crate::discovery::forecast_version;
 use crate::forecast::Forecast;
+use crate::utils::{write_atomic, Fnv64};
 use crate::Result;
-use bincode;
-use std::fs;
-use std::path::PathBuf;
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
 
 static LAST_JSON_FILENAME: &str = "last_forecast_url";
 static LAST_FORECAST_DATA: &str = "last_forecast.bin";
+static MANIFEST_FILENAME: &str = "manifest.json";
+static LOCK_FILENAME: &str = ".lock";
+static RENDERED_EXTENSIONS: [&str; 4] = ["pdf", "tex", "aux", "log"];
+const CACHE_MAGIC: [u8; 4] = *b"MSFC";
+// increment this whenever the serialized forecast changes
+const CACHE_FORMAT_VERSION: u32 = 9;
+pub static FORECAST_ARTIFACT: &str = LAST_FORECAST_DATA;
+/// Subfolder with the responses of conditional http requests
+pub static HTTP_CACHE_FOLDER: &str = "http";
+
+/// Header in front of the serialized forecast
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheHeader {
+    magic: [u8; 4],
+    format_version: u32,
+    crate_version: String,
+    created: i64,
+    checksum: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum View {
+    Day(usize),
+    Long,
+}
+
+impl View {
+    pub fn name(&self) -> String {
+        match self {
+            Self::Day(idx) => format!("{}", idx),
+            Self::Long => "long".to_string(),
+        }
+    }
+
+    pub fn pdf(&self) -> String {
+        format!("{}.pdf", self.name())
+    }
+}
+
+/// Records what every artifact in the cache folder was generated from.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Manifest {
+    pub artifacts: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub plz: u32,
+    pub version: String,
+    pub json_url: String,
+    pub created: i64,
+    #[serde(default)]
+    pub render_hash: Option<u64>,
+}
+
+impl ManifestEntry {
+    fn matches(&self, plz: u32, json_url: &str) -> bool {
+        self.plz == plz && self.version == forecast_version(json_url) && self.json_url == json_url
+    }
+}
+
+/// Advisory lock on the whole cache folder. The lock is released when this is dropped.
+pub struct CacheLock {
+    file: File,
+}
+
+impl Drop for CacheLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+/// Lock the cache folder. If another instance holds the lock, wait until it is done.
+pub fn lock(cache_folder: &str) -> Result<CacheLock> {
+    let mut p = PathBuf::from(cache_folder);
+    if !p.is_dir() {
+        fs::create_dir_all(&p)?;
+    }
+    p.push(LOCK_FILENAME);
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(p)?;
+    if file.try_lock_exclusive().is_err() {
+        println!("Waiting for another instance to finish...");
+        file.lock_exclusive()?;
+    }
+    Ok(CacheLock { file })
+}
+
+pub fn location_folder(cache_folder: &str, plz: u32) -> String {
+    let mut p = PathBuf::from(cache_folder);
+    p.push(plz.to_string());
+    p.into_os_string().into_string().unwrap()
+}
 
 pub fn check_cache(cache_folder: &str) -> Result<Option<String>> {
     let mut p = PathBuf::from(cache_folder);
@@ -40,48 +142,384 @@ pub fn check_cache(cache_folder: &str) -> Result<Option<String>> {
     }
 }
 
+pub fn read_manifest(cache_folder: &str) -> Manifest {
+    let mut p = PathBuf::from(cache_folder);
+    p.push(MANIFEST_FILENAME);
+    // a missing or broken manifest means that nothing in the cache can be trusted
+    fs::read_to_string(p)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn write_manifest(cache_folder: &str, manifest: &Manifest) -> Result<()> {
+    let mut p = PathBuf::from(cache_folder);
+    p.push(MANIFEST_FILENAME);
+    write_atomic(&p, serde_json::to_string_pretty(manifest)?.as_bytes())?;
+    Ok(())
+}
+
+pub fn record_artifact(
+    cache_folder: &str,
+    artifact: &str,
+    plz: u32,
+    json_url: &str,
+    render_hash: Option<u64>,
+) -> Result<()> {
+    let mut manifest = read_manifest(cache_folder);
+    manifest.artifacts.insert(
+        artifact.to_string(),
+        ManifestEntry {
+            plz,
+            version: forecast_version(json_url),
+            json_url: json_url.to_string(),
+            created: chrono::Utc::now().timestamp(),
+            render_hash,
+        },
+    );
+    write_manifest(cache_folder, &manifest)
+}
+
 pub fn clear_cache(cache_folder: &str) -> Result<()> {
     let p = PathBuf::from(cache_folder);
-    // remove all .pdf files in this directory
+    // forget all artifacts
+    write_manifest(cache_folder, &Manifest::default())?;
+    // remove all rendered files and the leftovers of pdflatex in this directory
     for file in fs::read_dir(&p)? {
         let file = file?;
         let path = file.path();
-        if path.is_file() {
-            if path.extension() == Some(std::ffi::OsStr::new("pdf")) {
-                fs::remove_file(path)?;
-            }
+        let rendered = match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) => RENDERED_EXTENSIONS.contains(&ext),
+            None => false,
+        };
+        if path.is_file() && rendered {
+            fs::remove_file(path)?;
         }
     }
 
     Ok(())
 }
 
+/// Summary of the cache of a single location
+#[derive(Debug)]
+pub struct CachedLocation {
+    pub plz: u32,
+    pub folder: PathBuf,
+    pub forecast: Option<ManifestEntry>,
+    pub files: Vec<CachedFile>,
+}
+
+impl CachedLocation {
+    pub fn size(&self) -> u64 {
+        self.files.iter().map(|f| f.size).sum()
+    }
+
+    /// Time when the forecast was fetched, or when the folder was last modified.
+    pub fn last_update(&self) -> i64 {
+        match &self.forecast {
+            Some(entry) => entry.created,
+            None => self.files.iter().map(|f| f.modified).max().unwrap_or(0),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CachedFile {
+    pub name: String,
+    pub size: u64,
+    pub modified: i64,
+    pub manifest: Option<ManifestEntry>,
+}
+
+/// List the cache of all locations, sorted by PLZ
+pub fn list_cache(cache_folder: &str) -> Result<Vec<CachedLocation>> {
+    let mut result = Vec::new();
+    if !PathBuf::from(cache_folder).is_dir() {
+        return Ok(result);
+    }
+    for dir in fs::read_dir(cache_folder)? {
+        let dir = dir?;
+        let plz: u32 = match dir.file_name().to_str().and_then(|n| n.parse().ok()) {
+            Some(plz) if dir.path().is_dir() => plz,
+            _ => continue,
+        };
+        let folder = dir.path().into_os_string().into_string().unwrap();
+        let mut manifest = read_manifest(&folder);
+        let files = list_files(&dir.path(), &manifest)?;
+        result.push(CachedLocation {
+            plz,
+            folder: dir.path(),
+            forecast: manifest.artifacts.remove(FORECAST_ARTIFACT),
+            files,
+        });
+    }
+    result.sort_by_key(|l| l.plz);
+    Ok(result)
+}
+
+/// List all files in the http cache, sorted by name
+pub fn list_http_cache(cache_folder: &str) -> Result<Vec<CachedFile>> {
+    let folder = PathBuf::from(cache_folder).join(HTTP_CACHE_FOLDER);
+    if !folder.is_dir() {
+        return Ok(Vec::new());
+    }
+    list_files(&folder, &Manifest::default())
+}
+
+/// All files in the folder, sorted by name
+fn list_files(folder: &Path, manifest: &Manifest) -> Result<Vec<CachedFile>> {
+    let mut files = Vec::new();
+    for file in fs::read_dir(folder)? {
+        let file = file?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let name = file.file_name().to_string_lossy().into_owned();
+        let modified = metadata
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        files.push(CachedFile {
+            manifest: manifest.artifacts.get(&name).cloned(),
+            name,
+            size: metadata.len(),
+            modified,
+        });
+    }
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(files)
+}
+
+/// Remove the cache of all locations that were not updated within the last `max_age` seconds,
+/// and of all but the `keep` most recently updated locations. Returns the removed locations.
+pub fn prune_cache(
+    cache_folder: &str,
+    max_age: Option<i64>,
+    keep: Option<usize>,
+) -> Result<Vec<CachedLocation>> {
+    let now = chrono::Utc::now().timestamp();
+    let mut locations = list_cache(cache_folder)?;
+    // most recent first
+    locations.sort_by_key(|l| -l.last_update());
+    let mut removed = Vec::new();
+    for (i, location) in locations.into_iter().enumerate() {
+        let too_old = match max_age {
+            Some(max_age) => now - location.last_update() > max_age,
+            None => false,
+        };
+        let too_many = match keep {
+            Some(keep) => i >= keep,
+            None => false,
+        };
+        if too_old || too_many {
+            fs::remove_dir_all(&location.folder)?;
+            removed.push(location);
+        }
+    }
+    Ok(removed)
+}
+
+/// Remove all files in the http cache that were not written within the last `max_age` seconds.
+/// Returns the removed files.
+pub fn prune_http_cache(cache_folder: &str, max_age: i64) -> Result<Vec<CachedFile>> {
+    let now = chrono::Utc::now().timestamp();
+    let folder = PathBuf::from(cache_folder).join(HTTP_CACHE_FOLDER);
+    let mut removed = Vec::new();
+    for file in list_http_cache(cache_folder)? {
+        if now - file.modified > max_age {
+            fs::remove_file(folder.join(&file.name))?;
+            removed.push(file);
+        }
+    }
+    Ok(removed)
+}
+
+/// Remove everything in the cache folder (except the lock file).
+pub fn wipe_cache(cache_folder: &str) -> Result<()> {
+    if !PathBuf::from(cache_folder).is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(cache_folder)? {
+        let entry = entry?;
+        if entry.file_name() == LOCK_FILENAME {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
 pub fn set_current_json_url(cache_folder: &str, json_url: &str) -> Result<()> {
     let mut p = PathBuf::from(cache_folder);
     p.push(LAST_JSON_FILENAME);
-    fs::write(p, json_url)?;
+    write_atomic(&p, json_url.as_bytes())?;
     Ok(())
 }
 
 pub fn cache_forecast(cache_folder: &str, forecast: &Forecast) -> Result<()> {
     let mut p = PathBuf::from(cache_folder);
     p.push(LAST_FORECAST_DATA);
-    fs::write(p, bincode::serialize(forecast)?)?;
+    let payload = bincode::serialize(forecast)?;
+    let header = CacheHeader {
+        magic: CACHE_MAGIC,
+        format_version: CACHE_FORMAT_VERSION,
+        crate_version: env!("CARGO_PKG_VERSION").to_string(),
+        created: chrono::Utc::now().timestamp(),
+        checksum: checksum(&payload),
+    };
+    let mut data = bincode::serialize(&header)?;
+    data.extend(payload);
+    write_atomic(&p, &data)?;
     Ok(())
 }
 
-pub fn get_cached_forecast(cache_folder: &str) -> Result<Forecast> {
+/// Forecast read from the cache, together with the time when it was stored
+pub struct CachedForecast {
+    pub forecast: Forecast,
+    pub created: i64,
+}
+
+/// Read the cached forecast. If there is no cached forecast, or if it was written in an
+/// incompatible format, `None` is returned and the file is removed.
+pub fn get_cached_forecast(cache_folder: &str) -> Result<Option<CachedForecast>> {
     let mut p = PathBuf::from(cache_folder);
     p.push(LAST_FORECAST_DATA);
-    Ok(bincode::deserialize(&fs::read(p)?)?)
+    if !p.is_file() {
+        return Ok(None);
+    }
+    let data = fs::read(&p)?;
+    match decode_forecast(&data) {
+        Ok(fc) => Ok(Some(fc)),
+        Err(reason) => {
+            eprintln!("Discarding cached forecast: {}", reason);
+            fs::remove_file(p)?;
+            Ok(None)
+        }
+    }
+}
+
+fn decode_forecast(data: &[u8]) -> std::result::Result<CachedForecast, String> {
+    let mut reader = std::io::Cursor::new(data);
+    let header: CacheHeader = match bincode::deserialize_from(&mut reader) {
+        Ok(h) => h,
+        Err(_) => return Err("unknown format".to_string()),
+    };
+    if header.magic != CACHE_MAGIC {
+        return Err("unknown format".to_string());
+    }
+    if header.format_version != CACHE_FORMAT_VERSION
+        || header.crate_version != env!("CARGO_PKG_VERSION")
+    {
+        return Err(format!(
+            "written by version {} (format {}), expected version {} (format {})",
+            header.crate_version,
+            header.format_version,
+            env!("CARGO_PKG_VERSION"),
+            CACHE_FORMAT_VERSION
+        ));
+    }
+    let payload = &data[reader.position() as usize..];
+    if checksum(payload) != header.checksum {
+        return Err("checksum mismatch".to_string());
+    }
+    match bincode::deserialize(payload) {
+        Ok(forecast) => Ok(CachedForecast {
+            forecast,
+            created: header.created,
+        }),
+        Err(e) => Err(format!("cannot deserialize: {}", e)),
+    }
+}
+
+fn checksum(data: &[u8]) -> u64 {
+    let mut hasher = Fnv64::default();
+    hasher.write(data);
+    hasher.finish()
 }
 
-pub fn is_pdf_cached(cache_folder: &str, day_idx: usize, show_long: bool) -> bool {
+pub fn is_pdf_cached(
+    cache_folder: &str,
+    view: View,
+    plz: u32,
+    json_url: &str,
+    render_hash: u64,
+) -> Result<bool> {
     let mut p = PathBuf::from(cache_folder);
-    if show_long {
-        p.push("long.pdf");
-    } else {
-        p.push(format!("{}.pdf", day_idx));
+    p.push(view.pdf());
+    if !p.is_file() {
+        return Ok(false);
+    }
+    // only use the pdf if it was rendered from the current forecast of this location, and with
+    // the same template and settings
+    Ok(
+        match read_manifest(cache_folder).artifacts.get(&view.pdf()) {
+            Some(entry) => entry.matches(plz, json_url) && entry.render_hash == Some(render_hash),
+            None => false,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(format_version: u32, checksum: u64, payload: &[u8]) -> Vec<u8> {
+        let header = CacheHeader {
+            magic: CACHE_MAGIC,
+            format_version,
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            created: 0,
+            checksum,
+        };
+        let mut data = bincode::serialize(&header).unwrap();
+        data.extend(payload);
+        data
+    }
+
+    fn decode_error(data: &[u8]) -> String {
+        match decode_forecast(data) {
+            Ok(_) => panic!("expected the cached forecast to be rejected"),
+            Err(reason) => reason,
+        }
+    }
+
+    #[test]
+    fn decode_unknown_format() {
+        assert_eq!(decode_error(b"no forecast"), "unknown format");
+        let mut data = encode(CACHE_FORMAT_VERSION, checksum(b""), b"");
+        data[0] = b'X';
+        assert_eq!(decode_error(&data), "unknown format");
+    }
+
+    #[test]
+    fn decode_version_mismatch() {
+        let payload = b"payload";
+        let data = encode(CACHE_FORMAT_VERSION - 1, checksum(payload), payload);
+        let reason = decode_error(&data);
+        assert!(reason.starts_with("written by version"), "{}", reason);
+        assert!(
+            reason.contains(&format!("(format {})", CACHE_FORMAT_VERSION - 1)),
+            "{}",
+            reason
+        );
+    }
+
+    #[test]
+    fn decode_checksum_mismatch() {
+        let data = encode(CACHE_FORMAT_VERSION, checksum(b"payload"), b"pay1oad");
+        assert_eq!(decode_error(&data), "checksum mismatch");
+    }
+
+    #[test]
+    fn decode_invalid_payload() {
+        let payload = b"payload";
+        let data = encode(CACHE_FORMAT_VERSION, checksum(payload), payload);
+        assert!(decode_error(&data).starts_with("cannot deserialize"));
     }
-    p.is_file()
 }
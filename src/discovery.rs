@@ -0,0 +1,215 @@
+/*
+ * Meteoschweiz: Render meteo data from meteoschweiz.admin.ch
+ * Copyright (C) 2020  Tibor Schneider
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::http::Client;
+use crate::{Error, Result};
+
+use scraper::{Html, Selector};
+use std::fmt;
+use std::str::FromStr;
+
+pub const PATH_WITH_JSON_LINK: &str = "/home.html?tab=overview";
+const PATH_VERSIONS_JSON: &str = "/product/output/versions.json";
+const VERSIONS_JSON_KEY: &str = "forecast-chart";
+const CSS_SELECTOR_STR: &str = "div[class=\"overview__local-forecast clearfix\"]";
+
+/// Strategy to find the forecast chart json url
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    /// Scrape the overview html page for the `data-json-url` attribute
+    Scrape,
+    /// Lookup the current forecast version in the versions.json file
+    Versions,
+    /// Fill in the user-configured url template
+    Template,
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Scrape => write!(f, "scrape"),
+            Self::Versions => write!(f, "versions"),
+            Self::Template => write!(f, "template"),
+        }
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "scrape" => Ok(Self::Scrape),
+            "versions" => Ok(Self::Versions),
+            "template" => Ok(Self::Template),
+            s => Err(Error::DiscoveryError(format!(
+                "Unknown discovery strategy: {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// Result of a successful discovery
+#[derive(Debug, Clone)]
+pub struct Discovered {
+    pub json_url: String,
+    pub strategy: Strategy,
+}
+
+/// Try all strategies in the given order, and return the first url that could be found. If all
+/// strategies fail, the error contains the reason of every single one of them. If all of them
+/// failed to reach the server, the last transport error is returned instead.
+pub fn discover_json_url(
+    client: &Client,
+    base_url: &str,
+    plz: u32,
+    strategies: &[String],
+    url_template: Option<&str>,
+) -> Result<Discovered> {
+    let mut errors: Vec<String> = Vec::new();
+    let mut transport_error: Option<Error> = None;
+    let mut other_error = false;
+    for strategy in strategies {
+        let strategy: Strategy = strategy.parse()?;
+        let result = match strategy {
+            Strategy::Scrape => scrape_json_url(client, base_url, plz),
+            Strategy::Versions => versions_json_url(client, base_url, plz),
+            Strategy::Template => template_json_url(client, base_url, plz, url_template),
+        };
+        match result {
+            Ok(json_url) => return Ok(Discovered { json_url, strategy }),
+            Err(e) => {
+                eprintln!("Discovery strategy \"{}\" failed: {}", strategy, e);
+                errors.push(format!("{}: {}", strategy, e));
+                // an unconfigured template says nothing about the server
+                let unconfigured = matches!(strategy, Strategy::Template) && url_template.is_none();
+                if e.is_transport() {
+                    transport_error = Some(e);
+                } else if !unconfigured {
+                    other_error = true;
+                }
+            }
+        }
+    }
+    match transport_error {
+        Some(e) if !other_error => Err(e),
+        _ => Err(Error::DiscoveryError(format!(
+            "No strategy succeeded ({})",
+            errors.join("; ")
+        ))),
+    }
+}
+
+/// Scrape the overview page and extract the json url from the forecast box.
+fn scrape_json_url(client: &Client, base_url: &str, plz: u32) -> Result<String> {
+    // get the html page with the link to the json
+    let html_string =
+        client.get_text_cached(&format!("{}{}", base_url, PATH_WITH_JSON_LINK), None)?;
+
+    let fragment = Html::parse_fragment(&html_string);
+    let selector = match Selector::parse(CSS_SELECTOR_STR) {
+        Ok(s) => s,
+        Err(e) => return Err(Error::CSSError(format!("{:?}", e))),
+    };
+    let section: String = match fragment.select(&selector).next() {
+        Some(s) => s.html(),
+        None => {
+            return Err(Error::HtmlError(
+                "Scraper was unable to find the forecast box!",
+            ))
+        }
+    };
+
+    let json_path = match match section.split("data-json-url=\"").nth(1) {
+        Some(s) => s,
+        None => return Err(Error::HtmlError("Could not find \"data-json-url\"")),
+    }
+    .split('\"')
+    .next()
+    {
+        Some(s) => s,
+        None => return Err(Error::HtmlError("\"data-json-url\" has an odd format!")),
+    };
+
+    // replace the XXXX00.json at the end with the plz
+    match json_path.rfind('/') {
+        Some(pos) if json_path.ends_with(".json") => {
+            Ok(format!("{}{}/{}00.json", base_url, &json_path[..pos], plz))
+        }
+        _ => Err(Error::HtmlError("\"data-json-url\" has an odd format!")),
+    }
+}
+
+/// Lookup the forecast version in the versions.json file, and build the url from it.
+fn versions_json_url(client: &Client, base_url: &str, plz: u32) -> Result<String> {
+    let version = current_version(client, base_url)?;
+    Ok(format!(
+        "{}/product/output/{}/{}/de/{}00.json",
+        base_url, VERSIONS_JSON_KEY, version, plz
+    ))
+}
+
+/// Fill in the url template. If the template contains `{version}`, the version is looked up in
+/// the versions.json file.
+fn template_json_url(
+    client: &Client,
+    base_url: &str,
+    plz: u32,
+    url_template: Option<&str>,
+) -> Result<String> {
+    let template = match url_template {
+        Some(t) => t,
+        None => {
+            return Err(Error::DiscoveryError(
+                "No json_url_template configured".to_string(),
+            ))
+        }
+    };
+    let mut json_url = template
+        .replace("{base_url}", base_url)
+        .replace("{plz}", &plz.to_string());
+    if json_url.contains("{version}") {
+        json_url = json_url.replace("{version}", &current_version(client, base_url)?);
+    }
+    Ok(json_url)
+}
+
+/// Extract the forecast version (like `version__20200612_1108`) from the json url. If the url
+/// has an unknown format, the url itself is used as version.
+pub fn forecast_version(json_url: &str) -> String {
+    json_url
+        .split('/')
+        .find(|segment| segment.starts_with("version__"))
+        .unwrap_or(json_url)
+        .to_string()
+}
+
+/// Get the current version of the forecast chart from the versions.json file.
+fn current_version(client: &Client, base_url: &str) -> Result<String> {
+    let versions: serde_json::Value = serde_json::from_str(
+        &client.get_text_cached(&format!("{}{}", base_url, PATH_VERSIONS_JSON), None)?,
+    )?;
+    match versions.get(VERSIONS_JSON_KEY).and_then(|v| v.as_str()) {
+        Some(v) => Ok(v.to_string()),
+        None => Err(Error::DiscoveryError(format!(
+            "versions.json does not contain \"{}\"",
+            VERSIONS_JSON_KEY
+        ))),
+    }
+}
@@ -16,8 +16,6 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use thiserror;
-
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("IO Error: {0}")]
@@ -26,14 +24,29 @@ pub enum Error {
     IsahcError(#[from] isahc::Error),
     #[error("HTTP Error: {0}")]
     HttpError(#[from] http::Error),
+    #[error("HTTP status {status} for {url}")]
+    HttpStatusError { url: String, status: u16 },
+    #[error("No recorded response for {0}")]
+    ReplayError(String),
     #[error("HTML Error: {0}")]
     HtmlError(&'static str),
     #[error("CSS Error: {0}")]
     CSSError(String),
+    #[error("Discovery Error: {0}")]
+    DiscoveryError(String),
+    #[error("Location Error: {0}")]
+    LocationError(String),
     #[error("Json Error: {0}")]
     JsonError(#[from] serde_json::Error),
     #[error("Forecast Building error: {0}")]
     ForecastBuildError(&'static str),
+    #[error("Invalid forecast data on {day}, {field}[{index}]: {reason}")]
+    ForecastDataError {
+        day: String,
+        field: &'static str,
+        index: usize,
+        reason: String,
+    },
     #[error("PathExpand Error: {0}")]
     PathExpandError(#[from] shellexpand::LookupError<std::env::VarError>),
     #[error("UTF8 Error: {0}")]
@@ -44,8 +57,25 @@ pub enum Error {
     TimeError(&'static str),
     #[error("Bincode Error: {0}")]
     BincodeError(#[from] bincode::Error),
+    #[error("No forecast available in the cache")]
+    NoCachedForecast,
+    #[error("Argument Error: {0}")]
+    ArgumentError(String),
+    #[error("Icon Error: {0}")]
+    IconError(String),
     #[error("Pdflatex exited with error code: {0:?}")]
     PdflatexError(Option<i32>),
 }
 
+impl Error {
+    /// Whether meteoschweiz.admin.ch could not be reached, or did not answer properly. Only in
+    /// this case, the cached forecast may be used instead.
+    pub fn is_transport(&self) -> bool {
+        matches!(
+            self,
+            Error::IsahcError(_) | Error::HttpStatusError { .. } | Error::ReplayError(_)
+        )
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
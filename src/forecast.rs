@@ -16,78 +16,37 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use crate::discovery::{forecast_version, PATH_WITH_JSON_LINK};
+use crate::http::Client;
+use crate::location::Locality;
+use crate::symbol::WeatherSymbol;
+use crate::theme::Theme;
+use crate::wind::{self, Beaufort, Compass, WindRose};
 use crate::{Error, Result};
 
-use chrono::{self, TimeZone, Timelike};
-use isahc::prelude::*;
-use itertools::zip_eq as zip;
-use scraper::{Html, Selector};
+use chrono::{self, DateTime, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
-use serde_json;
+use std::collections::BTreeSet;
 
-const URL_WITH_JSON_LINK: &str = "https://www.meteoschweiz.admin.ch/home.html?tab=overview";
-const CSS_SELECTOR_STR: &str = "div[class=\"overview__local-forecast clearfix\"]";
-const HEADER_REFERER_K: &str = "Referer";
-const HEADER_REFERER_V: &str = URL_WITH_JSON_LINK;
-
-pub fn get_forecast_chart_json_url(plz: u32) -> Result<String> {
-    // get the html page with the link to the json
-    let mut response = isahc::get(URL_WITH_JSON_LINK)?;
-    let html_string = response.text()?;
-
-    let mut json_url: String = String::new();
-
-    {
-        let fragment = Html::parse_fragment(&html_string);
-        let selector = match Selector::parse(CSS_SELECTOR_STR) {
-            Ok(s) => s,
-            Err(e) => return Err(Error::CSSError(format!("{:?}", e))),
-        };
-        let section: String = match fragment.select(&selector).next() {
-            Some(s) => s.html(),
-            None => {
-                return Err(Error::HtmlError(
-                    "Scraper was unable to find the forecast box!",
-                ))
-            }
-        };
-
-        json_url.push_str(
-            &match match section.split("data-json-url=\"").take(2).last() {
-                Some(s) => s,
-                None => return Err(Error::HtmlError("Could not find \"data-json-url\"")),
-            }
-            .split('\"')
-            .next()
-            {
-                Some(s) => Ok(format!("https://www.meteoschweiz.admin.ch{}", s)),
-                None => Err(Error::HtmlError("\"data-json-url\" has an odd format!")),
-            }?,
-        );
-    }
-
-    // replace the plz
-    // remove the XXXX00.json form the end
-    json_url.truncate(json_url.len() - "800100.json".len());
-    // push plz: XXXX
-    json_url.push_str(&format!("{}", plz));
-    // push 00.json
-    json_url.push_str("00.json");
-
-    Ok(json_url)
-}
-
-pub fn fetch_forecast(json_url: &String, icon_folder: &str) -> Result<Forecast> {
-    let icon_folder = shellexpand::full(icon_folder)?.into_owned();
-    let client = HttpClient::new()?;
-    let request = Request::get(json_url)
-        .header(HEADER_REFERER_K, HEADER_REFERER_V)
-        .body(())?;
-    let mut response = client.send(request)?;
-
-    let json_string = response.text()?;
+/// Fetch and parse the forecast. In lenient mode, invalid data points are skipped with a warning,
+/// instead of failing.
+pub fn fetch_forecast(
+    client: &Client,
+    base_url: &str,
+    json_url: &str,
+    location: &Locality,
+    lenient: bool,
+) -> Result<Forecast> {
+    let referer = format!("{}{}", base_url, PATH_WITH_JSON_LINK);
+    let json_string = client.get_text(json_url, Some(&referer))?;
     let forecast_builder: ForecastBuilder = serde_json::from_str(&json_string)?;
-    forecast_builder.build(&icon_folder)
+    let meta = ForecastMeta::new(json_url, location);
+    let (forecast, warnings) = forecast_builder.build(meta, lenient)?;
+    for warning in warnings {
+        eprintln!("Skipped invalid data: {}", warning);
+    }
+    Ok(forecast)
 }
 
 #[derive(Debug, Serialize)]
@@ -96,85 +55,123 @@ pub struct ForecastLong {
     temp_min: i32,
     temp_max: i32,
     rain_max: i32,
+    wind_max: i32,
+    sunshine_hours: Vec<f64>,
+    wind_summary: Vec<String>,
     rainfall: Vec<ForecastValueMinMax>,
+    sunshine: Vec<ForecastValue>,
     temperature: Vec<ForecastValueMinMax>,
     icons: Vec<ForecastIcon>,
+    wind: Vec<ForecastWind>,
+    wind_gust_peak: Vec<ForecastValue>,
 }
 
 impl ForecastLong {
     pub fn from(fc: &Forecast) -> Self {
+        // time of the long view is in days since the start of the first day
         Self {
             day_labels: fc
+                .days
                 .iter()
                 .map(|x| x.day.as_ref())
                 .collect::<Vec<&str>>()
                 .join(","),
-            temp_min: fc.iter().fold(
+            temp_min: fc.days.iter().fold(
                 1000,
                 |x, day| if x < day.temp_min { x } else { day.temp_min },
             ),
-            temp_max: fc.iter().fold(
+            temp_max: fc.days.iter().fold(
                 -1000,
                 |x, day| if x > day.temp_max { x } else { day.temp_max },
             ),
-            rain_max: fc.iter().fold(
+            rain_max: fc.days.iter().fold(
                 -1000,
                 |x, day| if x > day.rain_max { x } else { day.rain_max },
             ),
-            rainfall: fix(fc
-                .iter()
-                .map(|x| x.rainfall.clone().into_iter())
-                .flatten()
-                .collect()),
-            temperature: fix(fc
-                .iter()
-                .map(|x| x.temperature.clone().into_iter())
-                .flatten()
-                .collect()),
+            wind_max: fc.days.iter().map(|day| day.wind_max).max().unwrap_or(20),
+            sunshine_hours: fc.days.iter().map(|day| day.sunshine_hours).collect(),
+            wind_summary: fc.days.iter().map(|day| day.wind_summary.clone()).collect(),
+            rainfall: fix(
+                fc.days
+                    .iter()
+                    .flat_map(|x| x.rainfall.clone().into_iter())
+                    .collect(),
+                fc,
+            ),
+            sunshine: fix(
+                fc.days
+                    .iter()
+                    .flat_map(|x| x.sunshine.clone().into_iter())
+                    .collect(),
+                fc,
+            ),
+            temperature: fix(
+                fc.days
+                    .iter()
+                    .flat_map(|x| x.temperature.clone().into_iter())
+                    .collect(),
+                fc,
+            ),
             icons: fix_icon(
-                fc.iter()
-                    .map(|x| x.icons.clone().into_iter())
-                    .flatten()
+                fc.days
+                    .iter()
+                    .flat_map(|x| x.icons.clone().into_iter())
+                    .collect(),
+                fc,
+            ),
+            wind: fix(
+                fc.days
+                    .iter()
+                    .flat_map(|x| x.wind.clone().into_iter())
                     .collect(),
+                fc,
+            ),
+            wind_gust_peak: fix(
+                fc.days
+                    .iter()
+                    .flat_map(|x| x.wind_gust_peak.clone().into_iter())
+                    .collect(),
+                fc,
             ),
         }
     }
 }
 
-fn fix<T>(v: Vec<T>) -> Vec<T>
+fn fix<T>(mut v: Vec<T>, fc: &Forecast) -> Vec<T>
 where
-    T: PartialEq + Timestamped,
+    T: Timestamped,
 {
-    let v = remove_duplicate(v);
-    fix_timestamps(v)
+    // the last value of every day is the same as the first one of the next day
+    v.dedup_by_key(|x| x.timestamp());
+    in_days(v, fc)
 }
 
-fn fix_icon<T>(v: Vec<T>) -> Vec<T>
+fn fix_icon<T>(v: Vec<T>, fc: &Forecast) -> Vec<T>
 where
     T: Timestamped,
 {
     let v = remove_every_second(v);
-    fix_timestamps(v)
+    in_days(v, fc)
 }
 
-fn fix_timestamps<T>(mut v: Vec<T>) -> Vec<T>
+/// Set the time to the number of days since the start of the first day. Days with a DST switch
+/// have 23 or 25 hours, so the fraction is relative to the length of the day the item is in.
+fn in_days<T>(mut v: Vec<T>, fc: &Forecast) -> Vec<T>
 where
     T: Timestamped,
 {
-    let mut h_offset = -24.0;
-    let mut current_t = 10000.0;
     for item in v.iter_mut() {
-        if item.time() < current_t {
-            h_offset += 24.0;
+        let t = item.timestamp();
+        let idx = fc.days.iter().rposition(|day| day.start <= t).unwrap_or(0);
+        if let Some(day) = fc.days.get(idx) {
+            item.set_time(idx as f64 + hours_since(t, day.start) / day.hours);
         }
-        current_t = item.time();
-        item.set_time((item.time() + h_offset) / 24.0);
     }
     v
 }
 
 fn remove_every_second<T>(v: Vec<T>) -> Vec<T> {
-    let mut result: Vec<T> = Vec::with_capacity((v.len() + 1) / 2);
+    let mut result: Vec<T> = Vec::with_capacity(v.len().div_ceil(2));
     let mut skip = false;
     for item in v.into_iter() {
         if !skip {
@@ -185,29 +182,133 @@ fn remove_every_second<T>(v: Vec<T>) -> Vec<T> {
     result
 }
 
-fn remove_duplicate<T>(v: Vec<T>) -> Vec<T>
-where
-    T: PartialEq,
-{
-    let mut result: Vec<T> = Vec::with_capacity(v.len() - 6);
-    let mut iter = v.into_iter().peekable();
-    while let Some(item) = iter.next() {
-        if let Some(next) = iter.peek() {
-            if next != &item {
-                result.push(item);
-            }
-        } else {
-            result.push(item);
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Forecast {
+    pub meta: ForecastMeta,
+    pub days: Vec<ForecastDay>,
+}
+
+/// Where the forecast comes from, and when it was issued and fetched
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ForecastMeta {
+    /// Version of the forecast, like `version__20200612_1108`
+    version: String,
+    location: Locality,
+    /// Time of the model run, if meteoschweiz reports it
+    issued: Option<ForecastTime>,
+    fetched: ForecastTime,
+    /// Time window covered by every day of the forecast
+    validity: Vec<Validity>,
+}
+
+impl ForecastMeta {
+    fn new(json_url: &str, location: &Locality) -> Self {
+        Self {
+            version: forecast_version(json_url),
+            location: location.clone(),
+            issued: None,
+            fetched: ForecastTime {
+                timestamp: Utc::now(),
+                time: String::new(),
+            },
+            validity: Vec::new(),
         }
     }
-    result
 }
 
-pub type Forecast = Vec<ForecastDay>;
+/// Absolute timestamp, and the local time of day, set by `localize`
+#[derive(Debug, Serialize, Deserialize)]
+struct ForecastTime {
+    timestamp: DateTime<Utc>,
+    time: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Validity {
+    day: String,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+}
+
+/// All weather symbols used in the forecast
+pub fn symbol_ids(fc: &Forecast) -> BTreeSet<u64> {
+    fc.days
+        .iter()
+        .flat_map(|day| day.icons.iter().map(|icon| icon.symbol.id))
+        .collect()
+}
+
+/// Slice the forecast into days of the given timezone, and set the time of every element to the
+/// hours since midnight. Days with a DST switch have 23 or 25 hours.
+pub fn localize(fc: &mut Forecast, tz: Tz) {
+    let meta = &mut fc.meta;
+    for t in meta.issued.iter_mut().chain(Some(&mut meta.fetched)) {
+        t.time = t.timestamp.with_timezone(&tz).format("%H:%M").to_string();
+    }
+    for (day, validity) in fc.days.iter_mut().zip(meta.validity.iter()) {
+        // the day is the one in the middle of its validity window
+        let date = (validity.from + (validity.to - validity.from) / 2)
+            .with_timezone(&tz)
+            .date_naive();
+        let (start, hours, xticks) = day_axis(tz, date);
+        day.start = start;
+        day.hours = hours;
+        day.xticks = xticks;
+        set_times(&mut day.rainfall, start);
+        set_times(&mut day.sunshine, start);
+        set_times(&mut day.temperature, start);
+        set_times(&mut day.icons, start);
+        set_times(&mut day.wind, start);
+        set_times(&mut day.wind_gust_peak, start);
+    }
+}
+
+fn set_times<T: Timestamped>(v: &mut [T], start: DateTime<Utc>) {
+    for item in v.iter_mut() {
+        item.set_time(hours_since(item.timestamp(), start));
+    }
+}
+
+/// Local midnight of the date, the length of the day in hours (23 or 25 on a DST switch), and the
+/// position of 06:00, 12:00 and 18:00 in hours since midnight.
+fn day_axis(tz: Tz, date: NaiveDate) -> (DateTime<Utc>, f64, Vec<f64>) {
+    let start = local_time(tz, date, 0);
+    let hours = hours_since(local_time(tz, date.succ_opt().unwrap_or(date), 0), start);
+    let xticks = [6, 12, 18]
+        .iter()
+        .map(|h| hours_since(local_time(tz, date, *h), start))
+        .collect();
+    (start, hours, xticks)
+}
+
+/// Time of the full hour on the given date. Times in the gap of a DST switch do not exist, the
+/// end of the gap is used instead.
+fn local_time(tz: Tz, date: NaiveDate, hour: u32) -> DateTime<Utc> {
+    let t = date.and_hms_opt(hour, 0, 0).unwrap_or_default();
+    tz.from_local_datetime(&t)
+        .earliest()
+        .or_else(|| tz.from_local_datetime(&(t + Duration::hours(1))).earliest())
+        .map(|x| x.with_timezone(&Utc))
+        .unwrap_or_else(|| Utc.from_utc_datetime(&t))
+}
+
+/// Set the icon of every weather symbol in the forecast from the theme
+pub fn apply_theme(fc: &mut Forecast, theme: &Theme) {
+    for day in fc.days.iter_mut() {
+        for icon in day.icons.iter_mut() {
+            icon.icon = theme.icon(&icon.symbol);
+        }
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ForecastDay {
     day: String,
+    /// Local midnight, set by `localize`
+    start: DateTime<Utc>,
+    /// Length of the day in hours, and the position of 06:00, 12:00 and 18:00
+    hours: f64,
+    xticks: Vec<f64>,
     rainfall: Vec<ForecastValueMinMax>,
     sunshine: Vec<ForecastValue>,
     temperature: Vec<ForecastValueMinMax>,
@@ -217,23 +318,35 @@ pub struct ForecastDay {
     temp_min: i32,
     temp_max: i32,
     rain_max: i32,
+    /// Upper end of the wind axis, in km/h
+    wind_max: i32,
+    /// Total hours of sunshine of the day
+    sunshine_hours: f64,
+    wind_rose: Vec<WindRose>,
+    /// Like "NE 3 Bft, gusts 45 km/h"
+    wind_summary: String,
 }
 
+/// Every element has an absolute timestamp, and a time in hours (or days) relative to the start
+/// of the view, used for plotting. The time depends on the timezone, and is set by `localize`.
 trait Timestamped {
-    fn time(&self) -> f64;
+    fn timestamp(&self) -> DateTime<Utc>;
     fn set_time(&mut self, new_time: f64);
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct ForecastWind {
+    timestamp: DateTime<Utc>,
     time: f64,
     strength: f64,
-    direction: String,
+    /// None if the direction code is unknown
+    direction: Option<Compass>,
+    beaufort: Beaufort,
 }
 
 impl Timestamped for ForecastWind {
-    fn time(&self) -> f64 {
-        self.time
+    fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
     }
     fn set_time(&mut self, new_time: f64) {
         self.time = new_time
@@ -242,13 +355,14 @@ impl Timestamped for ForecastWind {
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub struct ForecastValue {
+    timestamp: DateTime<Utc>,
     time: f64,
     value: f64,
 }
 
 impl Timestamped for ForecastValue {
-    fn time(&self) -> f64 {
-        self.time
+    fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
     }
     fn set_time(&mut self, new_time: f64) {
         self.time = new_time
@@ -256,14 +370,15 @@ impl Timestamped for ForecastValue {
 }
 
 impl ForecastValue {
-    fn from(obj: &Vec<I64orF64>) -> Result<Self> {
+    fn from(obj: &[I64orF64]) -> Result<Self> {
         if obj.len() != 2 {
             Err(Error::ForecastBuildError(
                 "ForecastValue requires a vector with 2 elements!",
             ))
         } else {
             Ok(Self {
-                time: timestamp_to_time(obj[0].to_i64()?)?,
+                timestamp: to_datetime(obj[0].to_i64()?)?,
+                time: 0.0,
                 value: obj[1].as_f64(),
             })
         }
@@ -272,6 +387,7 @@ impl ForecastValue {
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub struct ForecastValueMinMax {
+    timestamp: DateTime<Utc>,
     time: f64,
     value: f64,
     low: f64,
@@ -279,8 +395,8 @@ pub struct ForecastValueMinMax {
 }
 
 impl Timestamped for ForecastValueMinMax {
-    fn time(&self) -> f64 {
-        self.time
+    fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
     }
     fn set_time(&mut self, new_time: f64) {
         self.time = new_time
@@ -288,7 +404,7 @@ impl Timestamped for ForecastValueMinMax {
 }
 
 impl ForecastValueMinMax {
-    fn from(value_obj: &Vec<I64orF64>, range_obj: &Vec<I64orF64>) -> Result<Self> {
+    fn from(value_obj: &[I64orF64], range_obj: &[I64orF64]) -> Result<Self> {
         if value_obj.len() != 2 {
             Err(Error::ForecastBuildError(
                 "ForecastValue requires a vector with 2 elements!",
@@ -303,7 +419,8 @@ impl ForecastValueMinMax {
             ))
         } else {
             Ok(Self {
-                time: timestamp_to_time(value_obj[0].to_i64()?)?,
+                timestamp: to_datetime(value_obj[0].to_i64()?)?,
+                time: 0.0,
                 value: value_obj[1].as_f64(),
                 low: range_obj[1].as_f64(),
                 high: range_obj[2].as_f64(),
@@ -314,13 +431,15 @@ impl ForecastValueMinMax {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ForecastIcon {
+    timestamp: DateTime<Utc>,
     time: f64,
+    symbol: WeatherSymbol,
     icon: String,
 }
 
 impl Timestamped for ForecastIcon {
-    fn time(&self) -> f64 {
-        self.time
+    fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
     }
     fn set_time(&mut self, new_time: f64) {
         self.time = new_time
@@ -334,50 +453,124 @@ struct ForecastBuilder {
 }
 
 impl ForecastBuilder {
-    fn build(self, icon_path: &str) -> Result<Forecast> {
-        let mut result = Forecast::new();
+    fn build(self, mut meta: ForecastMeta, lenient: bool) -> Result<(Forecast, Vec<Error>)> {
+        let mut check = Check::new(lenient);
+        let mut result = Vec::new();
         let mut days = self.days.into_iter().peekable();
         while let Some(day) = days.next() {
+            let name = day.day_string.as_str();
+            let date = |field, timestamp| {
+                to_datetime(timestamp).map_err(|e| Error::ForecastDataError {
+                    day: name.to_string(),
+                    field,
+                    index: 0,
+                    reason: e.to_string(),
+                })
+            };
+            // the start of the day is moved to local midnight by `localize`
+            let start = date("min_date", day.min_date)?;
+            meta.validity.push(Validity {
+                day: name.to_string(),
+                from: start,
+                to: date("max_date", day.max_date)?,
+            });
+            if let (None, Some(current_time)) = (&meta.issued, day.current_time) {
+                meta.issued = Some(ForecastTime {
+                    timestamp: date("current_time", current_time)?,
+                    time: day.current_time_string.clone().unwrap_or_default(),
+                });
+            }
             let mut parsed_day = ForecastDay {
-                day: day.day_string,
-                rainfall: zip(day.variance_rain.iter(), day.rainfall.iter())
-                    .map(|(range, value)| ForecastValueMinMax::from(value, range).unwrap())
-                    .collect(),
-                sunshine: day
-                    .sunshine
-                    .iter()
-                    .map(|s| ForecastValue::from(s).unwrap())
-                    .collect(),
-                temperature: zip(day.variance_range.iter(), day.temperature.iter())
-                    .map(|(range, value)| ForecastValueMinMax::from(value, range).unwrap())
-                    .collect(),
-                icons: day
-                    .symbols
-                    .into_iter()
-                    .map(|s| s.build(icon_path).unwrap())
-                    .collect(),
-                wind: day.wind.build()?,
-                wind_gust_peak: day.wind_gust_peak.build()?,
+                day: name.to_string(),
+                start,
+                hours: 24.0,
+                xticks: vec![6.0, 12.0, 18.0],
+                rainfall: check.min_max(
+                    name,
+                    ("rainfall", &day.rainfall),
+                    ("variance_rain", &day.variance_rain),
+                )?,
+                sunshine: check.values(name, "sunshine", &day.sunshine)?,
+                temperature: check.min_max(
+                    name,
+                    ("temperature", &day.temperature),
+                    ("variance_range", &day.variance_range),
+                )?,
+                icons: check.elements(name, "symbols", &day.symbols, |s| s.build())?,
+                wind: day.wind.build(name, &mut check)?,
+                wind_gust_peak: check.values(name, "wind_gust_peak", &day.wind_gust_peak.data)?,
                 temp_min: 0,
                 temp_max: 0,
                 rain_max: 10,
+                wind_max: 20,
+                sunshine_hours: 0.0,
+                wind_rose: Vec::new(),
+                wind_summary: String::new(),
             };
-            // push the next entry to the current one
-            if let Some(next_day) = days.peek() {
-                parsed_day.rainfall.push(ForecastValueMinMax::from(
-                    &next_day.rainfall[0],
-                    &next_day.variance_rain[0],
-                )?);
+            // sunshine is given in minutes per hour
+            parsed_day.sunshine_hours =
+                parsed_day.sunshine.iter().map(|s| s.value).sum::<f64>() / 60.0;
+            parsed_day.wind_rose = wind::wind_rose(
+                parsed_day
+                    .wind
+                    .iter()
+                    .filter_map(|w| Some((w.direction?, w.strength))),
+            );
+            parsed_day.wind_summary = wind::summary(
+                &parsed_day.wind_rose,
                 parsed_day
-                    .sunshine
-                    .push(ForecastValue::from(&next_day.sunshine[0])?);
-                parsed_day.temperature.push(ForecastValueMinMax::from(
-                    &next_day.temperature[0],
-                    &next_day.variance_range[0],
-                )?);
-                parsed_day.rainfall.last_mut().unwrap().time += 24.0;
-                parsed_day.sunshine.last_mut().unwrap().time += 24.0;
-                parsed_day.temperature.last_mut().unwrap().time += 24.0;
+                    .wind
+                    .iter()
+                    .map(|w| w.beaufort)
+                    .max()
+                    .unwrap_or_default(),
+                parsed_day
+                    .wind_gust_peak
+                    .iter()
+                    .map(|g| g.value)
+                    .reduce(f64::max),
+            );
+            // push the first entry of the next day to the current one, such that the curves reach
+            // midnight. Invalid entries are reported when building the next day.
+            if let Some(next_day) = days.peek() {
+                if let (Some(value), Some(range)) =
+                    (next_day.rainfall.first(), next_day.variance_rain.first())
+                {
+                    if let Ok(v) = ForecastValueMinMax::from(value, range) {
+                        parsed_day.rainfall.push(v);
+                    }
+                }
+                if let Some(value) = next_day.sunshine.first() {
+                    if let Ok(v) = ForecastValue::from(value) {
+                        parsed_day.sunshine.push(v);
+                    }
+                }
+                if let (Some(value), Some(range)) = (
+                    next_day.temperature.first(),
+                    next_day.variance_range.first(),
+                ) {
+                    if let Ok(v) = ForecastValueMinMax::from(value, range) {
+                        parsed_day.temperature.push(v);
+                    }
+                }
+                if let Some(v) = next_day.wind.first() {
+                    parsed_day.wind.push(v);
+                }
+                if let Some(value) = next_day.wind_gust_peak.data.first() {
+                    if let Ok(v) = ForecastValue::from(value) {
+                        parsed_day.wind_gust_peak.push(v);
+                    }
+                }
+            }
+
+            // the scales of the plot cannot be computed without any temperature
+            if parsed_day.temperature.is_empty() {
+                return Err(Error::ForecastDataError {
+                    day: name.to_string(),
+                    field: "temperature",
+                    index: 0,
+                    reason: "No valid values".to_string(),
+                });
             }
 
             let min_temp =
@@ -412,14 +605,138 @@ impl ForecastBuilder {
                 max_rain_round = 10;
             }
 
+            let max_wind = parsed_day
+                .wind
+                .iter()
+                .map(|w| w.strength)
+                .chain(parsed_day.wind_gust_peak.iter().map(|g| g.value))
+                .fold(0.0, f64::max);
+
             parsed_day.temp_min = min_temp_round;
+            parsed_day.wind_max = (((max_wind / 10.0) as i32 + 1) * 10).max(20);
             parsed_day.temp_max = max_temp_round;
             parsed_day.rain_max = max_rain_round;
 
             result.push(parsed_day);
         }
+        Ok((Forecast { meta, days: result }, check.warnings))
+    }
+}
+
+/// Decides what happens with invalid forecast data. In strict mode, the first invalid element
+/// is returned as an error. In lenient mode, invalid elements are skipped, and collected as
+/// warnings.
+struct Check {
+    lenient: bool,
+    warnings: Vec<Error>,
+}
+
+impl Check {
+    fn new(lenient: bool) -> Self {
+        Self {
+            lenient,
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Report an invalid element. It is returned as error in strict mode.
+    fn invalid(
+        &mut self,
+        day: &str,
+        field: &'static str,
+        index: usize,
+        reason: String,
+    ) -> Result<()> {
+        let e = Error::ForecastDataError {
+            day: day.to_string(),
+            field,
+            index,
+            reason,
+        };
+        if self.lenient {
+            self.warnings.push(e);
+            Ok(())
+        } else {
+            Err(e)
+        }
+    }
+
+    /// Returns the element if it is valid, and `None` if it is skipped.
+    fn element<T>(
+        &mut self,
+        day: &str,
+        field: &'static str,
+        index: usize,
+        element: Result<T>,
+    ) -> Result<Option<T>> {
+        match element {
+            Ok(x) => Ok(Some(x)),
+            Err(e) => {
+                let reason = match e {
+                    Error::ForecastBuildError(reason) | Error::TimeError(reason) => {
+                        reason.to_string()
+                    }
+                    e => e.to_string(),
+                };
+                self.invalid(day, field, index, reason)?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn elements<S, T, F>(
+        &mut self,
+        day: &str,
+        field: &'static str,
+        source: &[S],
+        build: F,
+    ) -> Result<Vec<T>>
+    where
+        F: Fn(&S) -> Result<T>,
+    {
+        let mut result = Vec::with_capacity(source.len());
+        for (i, s) in source.iter().enumerate() {
+            if let Some(x) = self.element(day, field, i, build(s))? {
+                result.push(x);
+            }
+        }
         Ok(result)
     }
+
+    fn values(
+        &mut self,
+        day: &str,
+        field: &'static str,
+        source: &[Vec<I64orF64>],
+    ) -> Result<Vec<ForecastValue>> {
+        self.elements(day, field, source, |v| ForecastValue::from(v))
+    }
+
+    /// Combine the values with their ranges. If there are more values than ranges (or vice
+    /// versa), the additional elements are invalid.
+    fn min_max(
+        &mut self,
+        day: &str,
+        (field, values): (&'static str, &[Vec<I64orF64>]),
+        (range_field, ranges): (&'static str, &[Vec<I64orF64>]),
+    ) -> Result<Vec<ForecastValueMinMax>> {
+        let len = values.len().min(ranges.len());
+        if values.len() != ranges.len() {
+            let reason = format!(
+                "{} has {} elements, but {} has {}",
+                field,
+                values.len(),
+                range_field,
+                ranges.len()
+            );
+            self.invalid(day, field, len, reason)?;
+        }
+        let pairs: Vec<(&Vec<I64orF64>, &Vec<I64orF64>)> =
+            values[..len].iter().zip(ranges[..len].iter()).collect();
+        self.elements(day, field, &pairs, |(value, range)| {
+            ForecastValueMinMax::from(value, range)
+        })
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -468,18 +785,12 @@ struct ForecastSymbolBuilder {
 }
 
 impl ForecastSymbolBuilder {
-    fn build(self, icon_path: &str) -> Result<ForecastIcon> {
+    fn build(&self) -> Result<ForecastIcon> {
         Ok(ForecastIcon {
-            time: timestamp_to_time(self.timestamp)?,
-            icon: format!(
-                "{}/{}.pdf",
-                icon_path,
-                if self.weather_symbol_id > 100 {
-                    self.weather_symbol_id - 100
-                } else {
-                    self.weather_symbol_id
-                }
-            ),
+            timestamp: to_datetime(self.timestamp)?,
+            time: 0.0,
+            symbol: WeatherSymbol::from(self.weather_symbol_id),
+            icon: String::new(),
         })
     }
 }
@@ -491,53 +802,80 @@ struct ForecastWindBuilder {
 }
 
 impl ForecastWindBuilder {
-    fn build(self) -> Result<Vec<ForecastWind>> {
+    fn build(self, day: &str, check: &mut Check) -> Result<Vec<ForecastWind>> {
         let mut result: Vec<ForecastWind> = Vec::with_capacity(self.data.len());
-        let mut data_iter = self.data.iter().peekable();
-        let mut symbol_iter = self.symbols.iter().peekable();
+        let symbols: Vec<(i64, Option<Compass>)> = self.symbols.iter().map(|s| s.build()).collect();
+        let mut symbol_iter = symbols.iter().peekable();
         let mut current_symbol = match symbol_iter.next() {
             None => {
-                return Err(Error::ForecastBuildError(
-                    "At least one wind symbol must exist",
-                ))
+                // without any direction, no wind can be shown
+                let reason = "At least one wind symbol must exist".to_string();
+                check.invalid(day, "wind.symbols", 0, reason)?;
+                return Ok(result);
             }
             Some(s) => s,
         };
 
-        if let Some(first_data_peek) = data_iter.peek() {
-            if first_data_peek.len() != 2
-                || first_data_peek[0].to_i64()? != current_symbol.timestamp
-            {
-                return Err(Error::ForecastBuildError(
-                    "The first symbol and the first measurement of wind does not match!",
-                ));
+        match self.data.first() {
+            Some(first) if first.len() == 2 && first[0] == I64orF64::I64(current_symbol.0) => {}
+            Some(_) => {
+                let reason =
+                    "The first symbol and the first measurement of wind does not match".to_string();
+                check.invalid(day, "wind.data", 0, reason)?;
+            }
+            None => {
+                let reason = "No values received for the wind".to_string();
+                check.invalid(day, "wind.data", 0, reason)?;
             }
-        } else {
-            return Err(Error::ForecastBuildError(
-                "No values received for the wind!",
-            ));
         }
 
-        while let Some(data) = data_iter.next() {
-            if data.len() != 2 {
-                return Err(Error::ForecastBuildError(
-                    "Wind data vector is expected to have length 2!",
-                ));
-            }
-            if let Some(next_symbol) = symbol_iter.peek() {
-                if next_symbol.timestamp <= data[0].to_i64()? {
-                    current_symbol = symbol_iter.next().unwrap();
+        for (i, data) in self.data.iter().enumerate() {
+            let point = match data.as_slice() {
+                [time, strength] => time.to_i64().map(|t| (t, strength.as_f64())),
+                _ => Err(Error::ForecastBuildError(
+                    "Wind data vector is expected to have length 2",
+                )),
+            };
+            let (timestamp, strength) = match check.element(day, "wind.data", i, point)? {
+                Some(p) => p,
+                None => continue,
+            };
+            while let Some(next_symbol) = symbol_iter.peek() {
+                if next_symbol.0 > timestamp {
+                    break;
                 }
+                current_symbol = symbol_iter.next().unwrap();
+            }
+            let timestamp = check.element(day, "wind.data", i, to_datetime(timestamp))?;
+            if let Some(timestamp) = timestamp {
+                result.push(ForecastWind {
+                    timestamp,
+                    time: 0.0,
+                    strength,
+                    direction: current_symbol.1,
+                    beaufort: Beaufort::from_kmh(strength),
+                });
             }
-            result.push(ForecastWind {
-                time: timestamp_to_time(data[0].to_i64()?)?,
-                strength: data[1].as_f64(),
-                direction: current_symbol.symbol_id.clone(),
-            });
         }
 
         Ok(result)
     }
+
+    /// First sample with the first direction, if it is valid
+    fn first(&self) -> Option<ForecastWind> {
+        let (timestamp, strength) = match self.data.first()?.as_slice() {
+            [time, strength] => (time.to_i64().ok()?, strength.as_f64()),
+            _ => return None,
+        };
+        let (_, direction) = self.symbols.first()?.build();
+        Some(ForecastWind {
+            timestamp: to_datetime(timestamp).ok()?,
+            time: 0.0,
+            strength,
+            direction,
+            beaufort: Beaufort::from_kmh(strength),
+        })
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -546,24 +884,174 @@ struct ForecastWindSymbolBuilder {
     symbol_id: String,
 }
 
+impl ForecastWindSymbolBuilder {
+    /// An unknown direction code only drops the direction, not the whole sample
+    fn build(&self) -> (i64, Option<Compass>) {
+        (self.timestamp, self.symbol_id.parse().ok())
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct ForecastGustBuilder {
     data: Vec<Vec<I64orF64>>,
 }
 
-impl ForecastGustBuilder {
-    fn build(self) -> Result<Vec<ForecastValue>> {
-        let mut result = Vec::new();
-        for data in self.data {
-            result.push(ForecastValue::from(&data)?)
-        }
-        Ok(result)
+fn to_datetime(timestamp: i64) -> Result<DateTime<Utc>> {
+    match Utc.timestamp_millis_opt(timestamp) {
+        chrono::LocalResult::Single(t) => Ok(t),
+        _ => Err(Error::TimeError("Invalid timestamp")),
     }
 }
 
-fn timestamp_to_time(timestamp: i64) -> Result<f64> {
-    let local_tz = chrono::Local;
-    let t = local_tz.timestamp(timestamp / 1000, 0);
-    let time = t.time();
-    Ok(time.hour() as f64 + ((time.minute() as f64 + (time.second() as f64 / 60.0)) / 60.0))
+fn hours_since(t: DateTime<Utc>, start: DateTime<Utc>) -> f64 {
+    (t - start).num_seconds() as f64 / 3600.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn day_axis_dst_switch() {
+        let tz = chrono_tz::Europe::Zurich;
+
+        let (start, hours, xticks) = day_axis(tz, NaiveDate::from_ymd_opt(2026, 3, 29).unwrap());
+        assert_eq!(start, Utc.with_ymd_and_hms(2026, 3, 28, 23, 0, 0).unwrap());
+        assert_eq!(hours, 23.0);
+        assert_eq!(xticks, vec![5.0, 11.0, 17.0]);
+
+        let (start, hours, xticks) = day_axis(tz, NaiveDate::from_ymd_opt(2026, 10, 25).unwrap());
+        assert_eq!(start, Utc.with_ymd_and_hms(2026, 10, 24, 22, 0, 0).unwrap());
+        assert_eq!(hours, 25.0);
+        assert_eq!(xticks, vec![7.0, 13.0, 19.0]);
+
+        let (_, hours, xticks) = day_axis(tz, NaiveDate::from_ymd_opt(2026, 7, 1).unwrap());
+        assert_eq!(hours, 24.0);
+        assert_eq!(xticks, vec![6.0, 12.0, 18.0]);
+    }
+
+    fn data(json: &str) -> Vec<Vec<I64orF64>> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    /// Day, field and index of the reported error
+    fn data_error<T: std::fmt::Debug>(result: Result<T>) -> (String, &'static str, usize) {
+        match result {
+            Err(Error::ForecastDataError {
+                day, field, index, ..
+            }) => (day, field, index),
+            r => panic!("expected a ForecastDataError, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn check_strict_fails_on_first_invalid_element() {
+        let mut check = Check::new(false);
+        let source = data("[[0, 1.5], [3600000], [7200000, 3]]");
+        let e = data_error(check.values("Mo", "rainfall", &source));
+        assert_eq!(e, ("Mo".to_string(), "rainfall", 1));
+    }
+
+    #[test]
+    fn check_lenient_skips_invalid_elements() {
+        let mut check = Check::new(true);
+        let source = data("[[0, 1.5], [3600000.5, 2], [7200000, 3]]");
+        let values = check.values("Mo", "rainfall", &source).unwrap();
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[1].value, 3.0);
+        assert_eq!(check.warnings.len(), 1);
+        match &check.warnings[0] {
+            Error::ForecastDataError { index, reason, .. } => {
+                assert_eq!(*index, 1);
+                assert_eq!(reason, "Expected integer, found float");
+            }
+            e => panic!("unexpected warning: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn check_min_max_length_mismatch() {
+        let values = data("[[0, 10], [3600000, 12], [7200000, 14]]");
+        let ranges = data("[[0, 8, 11], [3600000, 9, 13]]");
+
+        let mut check = Check::new(false);
+        let e = data_error(check.min_max("Di", ("temperature", &values), ("range", &ranges)));
+        assert_eq!(e, ("Di".to_string(), "temperature", 2));
+
+        let mut check = Check::new(true);
+        let result = check
+            .min_max("Di", ("temperature", &values), ("range", &ranges))
+            .unwrap();
+        assert_eq!(result.len(), 2);
+        assert_eq!((result[1].low, result[1].high), (9.0, 13.0));
+        assert_eq!(check.warnings.len(), 1);
+    }
+
+    #[test]
+    fn check_min_max_time_mismatch() {
+        let values = data("[[0, 10], [3600000, 12]]");
+        let ranges = data("[[0, 8, 11], [1800000, 9, 13]]");
+        let mut check = Check::new(false);
+        let e = data_error(check.min_max("Di", ("temperature", &values), ("range", &ranges)));
+        assert_eq!(e, ("Di".to_string(), "temperature", 1));
+    }
+
+    fn wind(json: &str) -> ForecastWindBuilder {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn wind_follows_the_symbols() {
+        let builder = wind(
+            r#"{"data": [[0, 5], [3600000, 15], [7200000, 25]],
+                "symbols": [{"timestamp": 0, "symbol_id": "N"},
+                            {"timestamp": 3600000, "symbol_id": "SW"}]}"#,
+        );
+        let mut check = Check::new(false);
+        let wind = builder.build("Mi", &mut check).unwrap();
+        let directions: Vec<Option<Compass>> = wind.iter().map(|w| w.direction).collect();
+        assert_eq!(
+            directions,
+            vec![Some(Compass::N), Some(Compass::Sw), Some(Compass::Sw)]
+        );
+        assert_eq!(wind[2].beaufort, Beaufort::from(4));
+    }
+
+    #[test]
+    fn wind_unknown_direction_is_kept() {
+        let builder = wind(
+            r#"{"data": [[0, 5], [3600000, 15]],
+                "symbols": [{"timestamp": 0, "symbol_id": "XX"},
+                            {"timestamp": 3600000, "symbol_id": "E"}]}"#,
+        );
+        let mut check = Check::new(false);
+        let wind = builder.build("Mi", &mut check).unwrap();
+        assert_eq!(wind.len(), 2);
+        assert_eq!(wind[0].direction, None);
+        assert_eq!(wind[1].direction, Some(Compass::E));
+        assert!(check.warnings.is_empty());
+    }
+
+    #[test]
+    fn wind_invalid_data() {
+        let json = r#"{"data": [[0, 5], [3600000, 15, 1]],
+                       "symbols": [{"timestamp": 0, "symbol_id": "N"}]}"#;
+
+        let mut check = Check::new(false);
+        let e = data_error(wind(json).build("Do", &mut check));
+        assert_eq!(e, ("Do".to_string(), "wind.data", 1));
+
+        let mut check = Check::new(true);
+        let wind = wind(json).build("Do", &mut check).unwrap();
+        assert_eq!(wind.len(), 1);
+        assert_eq!(check.warnings.len(), 1);
+    }
+
+    #[test]
+    fn wind_without_symbols() {
+        let json = r#"{"data": [[0, 5]], "symbols": []}"#;
+        let mut check = Check::new(false);
+        let e = data_error(wind(json).build("Fr", &mut check));
+        assert_eq!(e, ("Fr".to_string(), "wind.symbols", 0));
+    }
 }
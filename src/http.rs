@@ -0,0 +1,334 @@
+/*
+ * Meteoschweiz: Render meteo data from meteoschweiz.admin.ch
+ * Copyright (C) 2020  Tibor Schneider
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::cache::HTTP_CACHE_FOLDER;
+use crate::settings::Settings;
+use crate::utils::{write_atomic, Fnv64};
+use crate::{Error, Result};
+
+use isahc::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::hash::Hasher;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::Duration;
+
+const HEADER_REFERER: &str = "Referer";
+const HEADER_USER_AGENT: &str = "User-Agent";
+const HEADER_ETAG: &str = "ETag";
+const HEADER_LAST_MODIFIED: &str = "Last-Modified";
+const HEADER_IF_NONE_MATCH: &str = "If-None-Match";
+const HEADER_IF_MODIFIED_SINCE: &str = "If-Modified-Since";
+const STATUS_NOT_MODIFIED: u16 = 304;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Where the responses come from
+#[derive(Debug, Clone)]
+pub enum Mode {
+    /// Send all requests to the network
+    Live,
+    /// Send all requests to the network, and store every response in the directory
+    Record(PathBuf),
+    /// Serve all responses from the directory, without touching the network
+    Replay(PathBuf),
+}
+
+/// Metadata of a recorded response
+#[derive(Debug, Serialize, Deserialize)]
+struct Recording {
+    url: String,
+    status: u16,
+}
+
+/// Validators of a cached response, used for conditional requests
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Validators {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+struct Response {
+    status: u16,
+    body: Vec<u8>,
+    validators: Validators,
+}
+
+/// HTTP client shared by all requests to meteoschweiz.admin.ch
+pub struct Client {
+    client: HttpClient,
+    retries: u32,
+    mode: Mode,
+    http_cache: PathBuf,
+}
+
+impl Client {
+    pub fn new(settings: &Settings, mode: Mode) -> Result<Self> {
+        if let Mode::Record(dir) = &mode {
+            fs::create_dir_all(dir)?;
+        }
+        let mut builder = HttpClient::builder()
+            .timeout(Duration::from_secs(settings.http_timeout))
+            .connect_timeout(Duration::from_secs(settings.http_connect_timeout))
+            .default_header(HEADER_USER_AGENT, settings.user_agent.as_str());
+        if let Some(proxy) = settings.proxy.as_ref() {
+            let proxy: http::Uri = match proxy.parse() {
+                Ok(p) => p,
+                Err(_) => return Err(Error::ArgumentError(format!("Invalid proxy: {}", proxy))),
+            };
+            builder = builder.proxy(Some(proxy));
+        }
+        Ok(Self {
+            client: builder.build()?,
+            retries: settings.http_retries,
+            mode,
+            http_cache: PathBuf::from(&settings.cache_folder).join(HTTP_CACHE_FOLDER),
+        })
+    }
+
+    /// Get the body of the url as a string.
+    pub fn get_text(&self, url: &str, referer: Option<&str>) -> Result<String> {
+        Ok(String::from_utf8(self.get(url, referer)?).map_err(|e| e.utf8_error())?)
+    }
+
+    /// Get the body of the url as a string. The response is kept in the http cache, and
+    /// revalidated with a conditional request. Only use this for urls that do not change, like
+    /// the overview page, such that the cache does not grow. This is used for discovery, so
+    /// connection errors are not retried, and the fallback to the cached forecast stays fast.
+    pub fn get_text_cached(&self, url: &str, referer: Option<&str>) -> Result<String> {
+        let body = self.get_with(url, referer, true, false)?;
+        Ok(String::from_utf8(body).map_err(|e| e.utf8_error())?)
+    }
+
+    /// Get the body of the url. Failed requests are retried with exponential backoff, and any
+    /// status other than 2xx is reported as an error.
+    pub fn get(&self, url: &str, referer: Option<&str>) -> Result<Vec<u8>> {
+        self.get_with(url, referer, false, true)
+    }
+
+    fn get_with(
+        &self,
+        url: &str,
+        referer: Option<&str>,
+        cached: bool,
+        retry_connect: bool,
+    ) -> Result<Vec<u8>> {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            match self.try_get(url, referer, cached) {
+                Ok(body) => return Ok(body),
+                Err(e) if !retry_connect && is_connection_error(&e) => return Err(e),
+                Err(e) if attempt < self.retries && is_transient(&e) => {
+                    eprintln!("Request to {} failed: {}, retrying...", url, e);
+                    sleep(backoff);
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn try_get(&self, url: &str, referer: Option<&str>, cached: bool) -> Result<Vec<u8>> {
+        let (status, body) = match &self.mode {
+            Mode::Replay(dir) => replay(dir, url)?,
+            Mode::Live if cached => self.send_conditional(url, referer)?,
+            Mode::Live => {
+                let response = self.send(url, referer, None)?;
+                (response.status, response.body)
+            }
+            Mode::Record(dir) => {
+                // always send unconditional requests, such that the full response is recorded
+                let response = self.send(url, referer, None)?;
+                record(dir, url, response.status, &response.body)?;
+                (response.status, response.body)
+            }
+        };
+        if !(200..300).contains(&status) {
+            return Err(Error::HttpStatusError {
+                url: url.to_string(),
+                status,
+            });
+        }
+        Ok(body)
+    }
+
+    /// Send a conditional request if the response is already cached. If the server responds
+    /// with 304 (not modified), the cached response is used.
+    fn send_conditional(&self, url: &str, referer: Option<&str>) -> Result<(u16, Vec<u8>)> {
+        let cached = load_cached(&self.http_cache, url);
+        let response = self.send(url, referer, cached.as_ref().map(|(v, _)| v))?;
+        match cached {
+            Some((_, body)) if response.status == STATUS_NOT_MODIFIED => Ok((200, body)),
+            _ => {
+                let cacheable = response.validators.etag.is_some()
+                    || response.validators.last_modified.is_some();
+                if (200..300).contains(&response.status) && cacheable {
+                    store_cached(&self.http_cache, &response.validators, &response.body)?;
+                }
+                Ok((response.status, response.body))
+            }
+        }
+    }
+
+    fn send(
+        &self,
+        url: &str,
+        referer: Option<&str>,
+        validators: Option<&Validators>,
+    ) -> Result<Response> {
+        let mut request = Request::get(url);
+        if let Some(referer) = referer {
+            request = request.header(HEADER_REFERER, referer);
+        }
+        if let Some(validators) = validators {
+            if let Some(etag) = validators.etag.as_ref() {
+                request = request.header(HEADER_IF_NONE_MATCH, etag.as_str());
+            }
+            if let Some(last_modified) = validators.last_modified.as_ref() {
+                request = request.header(HEADER_IF_MODIFIED_SINCE, last_modified.as_str());
+            }
+        }
+        let mut response = self.client.send(request.body(())?)?;
+        let mut body = Vec::new();
+        response.body_mut().read_to_end(&mut body)?;
+        let header = |name: &str| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string())
+        };
+        Ok(Response {
+            status: response.status().as_u16(),
+            validators: Validators {
+                url: url.to_string(),
+                etag: header(HEADER_ETAG),
+                last_modified: header(HEADER_LAST_MODIFIED),
+            },
+            body,
+        })
+    }
+}
+
+/// Remove the cached response of the url from the http cache, if it exists.
+pub fn forget_cached(cache_folder: &str, url: &str) -> Result<()> {
+    let dir = PathBuf::from(cache_folder).join(HTTP_CACHE_FOLDER);
+    let name = recording_name(url);
+    for ext in &["json", "body"] {
+        let p = dir.join(format!("{}.{}", name, ext));
+        if p.is_file() {
+            fs::remove_file(p)?;
+        }
+    }
+    Ok(())
+}
+
+fn load_cached(dir: &Path, url: &str) -> Option<(Validators, Vec<u8>)> {
+    let mut p = dir.to_path_buf();
+    let name = recording_name(url);
+    p.push(format!("{}.json", name));
+    let validators: Validators = serde_json::from_str(&fs::read_to_string(&p).ok()?).ok()?;
+    p.pop();
+    p.push(format!("{}.body", name));
+    Some((validators, fs::read(p).ok()?))
+}
+
+fn store_cached(dir: &Path, validators: &Validators, body: &[u8]) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    let mut p = dir.to_path_buf();
+    let name = recording_name(&validators.url);
+    p.push(format!("{}.body", name));
+    write_atomic(&p, body)?;
+    p.pop();
+    p.push(format!("{}.json", name));
+    write_atomic(&p, serde_json::to_string_pretty(validators)?.as_bytes())
+}
+
+/// Filename of the recorded or cached response of an url. It is readable, and unique thanks to
+/// the hash.
+fn recording_name(url: &str) -> String {
+    let mut hasher = Fnv64::default();
+    hasher.write(url.as_bytes());
+    let readable: String = url
+        .split("://")
+        .last()
+        .unwrap_or(url)
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let skip = readable.len().saturating_sub(80);
+    let readable = readable[skip..].trim_start_matches('.');
+    format!("{}-{:016x}", readable, hasher.finish())
+}
+
+fn record(dir: &Path, url: &str, status: u16, body: &[u8]) -> Result<()> {
+    let mut p = dir.to_path_buf();
+    let name = recording_name(url);
+    p.push(format!("{}.body", name));
+    write_atomic(&p, body)?;
+    p.pop();
+    p.push(format!("{}.json", name));
+    let recording = Recording {
+        url: url.to_string(),
+        status,
+    };
+    write_atomic(&p, serde_json::to_string_pretty(&recording)?.as_bytes())
+}
+
+fn replay(dir: &Path, url: &str) -> Result<(u16, Vec<u8>)> {
+    let mut p = dir.to_path_buf();
+    let name = recording_name(url);
+    p.push(format!("{}.json", name));
+    if !p.is_file() {
+        return Err(Error::ReplayError(url.to_string()));
+    }
+    let recording: Recording = serde_json::from_str(&fs::read_to_string(&p)?)?;
+    p.pop();
+    p.push(format!("{}.body", name));
+    Ok((recording.status, fs::read(p)?))
+}
+
+/// The server could not be reached at all.
+fn is_connection_error(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::IsahcError(isahc::Error::ConnectFailed)
+            | Error::IsahcError(isahc::Error::CouldntResolveHost)
+            | Error::IsahcError(isahc::Error::CouldntResolveProxy)
+            | Error::IsahcError(isahc::Error::Timeout)
+    )
+}
+
+/// Network errors, server errors and rate limiting might go away when trying again.
+fn is_transient(error: &Error) -> bool {
+    match error {
+        Error::IsahcError(_) => true,
+        Error::HttpStatusError { status, .. } => *status >= 500 || *status == 429,
+        _ => false,
+    }
+}
@@ -0,0 +1,244 @@
+/*
+ * Meteoschweiz: Render meteo data from meteoschweiz.admin.ch
+ * Copyright (C) 2020  Tibor Schneider
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::http::Client;
+use crate::theme::{Theme, ThemeKind, DEFAULT_THEME, MONO_THEME};
+use crate::utils::write_atomic;
+use crate::{Error, Result};
+
+use resvg::tiny_skia;
+use resvg::usvg::{self, TreeParsing, TreePostProc};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+static ICON_PATH: &str = "/etc/designs/meteoswiss/assets/images/icons/meteo/weather-symbols/";
+// width of the rendered png icons in pixels
+const PNG_WIDTH: u32 = 128;
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+type Bundled = &'static [(u64, &'static [u8])];
+
+macro_rules! bundled_icons {
+    ($($id:literal),*) => {
+        /// Default icons of all weather symbols, compiled into the binary (day and night)
+        static BUNDLED_ICONS: Bundled =
+            &[$(($id, include_bytes!(concat!("../assets/icons/", $id, ".svg")))),*];
+        /// Monochrome icons of all weather symbols, for e-ink displays
+        static BUNDLED_MONO_ICONS: Bundled =
+            &[$(($id, include_bytes!(concat!("../assets/icons/mono/", $id, ".svg")))),*];
+    };
+}
+
+bundled_icons![
+    1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26,
+    27, 28, 29, 30, 31, 32, 33, 34, 35, 101, 102, 103, 104, 105, 106, 107, 108, 109, 110, 111, 112,
+    113, 114, 115, 116, 117, 118, 119, 120, 121, 122, 123, 124, 125, 126, 127, 128, 129, 130, 131,
+    132, 133, 134, 135
+];
+
+/// Icons compiled into the binary for the theme
+fn bundled(theme: &Theme) -> Bundled {
+    match theme.name.as_str() {
+        DEFAULT_THEME => BUNDLED_ICONS,
+        MONO_THEME => BUNDLED_MONO_ICONS,
+        _ => &[],
+    }
+}
+
+/// All icons of the given weather symbols that are missing or broken in the theme
+pub fn missing_icons(theme: &Theme, ids: &BTreeSet<u64>) -> Vec<u64> {
+    if theme.kind == ThemeKind::Text {
+        return Vec::new();
+    }
+    let folder = PathBuf::from(&theme.folder);
+    ids.iter()
+        .cloned()
+        .filter(|id| !is_icon_valid(&folder, &theme.file_stem(*id)))
+        .collect()
+}
+
+/// Check if the theme folder holds original icons, i.e. svg files of weather symbols that differ
+/// from the bundled substitutes.
+pub fn holds_originals(theme: &Theme) -> bool {
+    let entries = match fs::read_dir(&theme.folder) {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+    entries.filter_map(|e| e.ok()).any(|entry| {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("svg") {
+            return false;
+        }
+        let id: u64 = match path.file_stem().and_then(|s| s.to_str()?.parse().ok()) {
+            Some(id) => id,
+            None => return false,
+        };
+        match bundled(theme).iter().find(|(i, _)| *i == id) {
+            Some((_, svg)) => fs::read(&path).map(|c| c != *svg).unwrap_or(false),
+            None => true,
+        }
+    })
+}
+
+/// Make sure that the icons of all given weather symbols exist in the theme. Missing icons are
+/// converted from the svg in the theme folder, or from the bundled svg if `use_bundled` is set.
+/// Returns the symbols that are still missing.
+pub fn install_icons(
+    theme: &Theme,
+    ids: &BTreeSet<u64>,
+    use_bundled: bool,
+) -> Result<BTreeSet<u64>> {
+    let folder = PathBuf::from(&theme.folder);
+    let mut missing = BTreeSet::new();
+    for id in missing_icons(theme, ids) {
+        let stem = theme.file_stem(id);
+        if let Ok(svg) = fs::read(folder.join(format!("{}.svg", stem))) {
+            match convert_icon(&svg, &folder, &stem) {
+                Ok(()) => continue,
+                Err(e) => eprintln!("Cannot convert icon {}: {}", stem, e),
+            }
+        }
+        match bundled(theme).iter().find(|(i, _)| *i == id) {
+            Some((_, svg)) if use_bundled => {
+                fs::create_dir_all(&folder)?;
+                write_atomic(&folder.join(format!("{}.svg", stem)), svg)?;
+                convert_icon(svg, &folder, &stem)?;
+            }
+            _ => {
+                missing.insert(id);
+            }
+        }
+    }
+    Ok(missing)
+}
+
+/// Download fresh icons from meteoschweiz.admin.ch for all bundled symbols and all symbols
+/// already in the icon folder, replacing the existing ones.
+pub fn update_icons(client: &Client, base_url: &str, icon_folder: &str) -> Result<()> {
+    let folder = PathBuf::from(icon_folder);
+    let mut ids: BTreeSet<u64> = BUNDLED_ICONS.iter().map(|(id, _)| *id).collect();
+    if folder.is_dir() {
+        for entry in fs::read_dir(&folder)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("svg") {
+                continue;
+            }
+            if let Some(id) = path.file_stem().and_then(|s| s.to_str()?.parse().ok()) {
+                ids.insert(id);
+            }
+        }
+    }
+    download_icons(client, base_url, &folder, ids)
+}
+
+/// Download the icons of the given weather symbols into the folder of the default theme, and
+/// convert them to pdf and png.
+pub fn fetch_icons(
+    client: &Client,
+    base_url: &str,
+    icon_folder: &str,
+    ids: &BTreeSet<u64>,
+) -> Result<()> {
+    download_icons(
+        client,
+        base_url,
+        &PathBuf::from(icon_folder),
+        ids.iter().cloned(),
+    )
+}
+
+/// Download the icons and convert them. Icons that fail are reported, but do not stop the others
+/// from being processed.
+fn download_icons(
+    client: &Client,
+    base_url: &str,
+    folder: &Path,
+    ids: impl IntoIterator<Item = u64>,
+) -> Result<()> {
+    // if it is not a directory, recursively generate it
+    if !folder.is_dir() {
+        fs::create_dir_all(folder)?;
+    }
+
+    let mut failed: Vec<String> = Vec::new();
+    for id in ids {
+        println!("Downloading and converting icon {}", id);
+        if let Err(e) = fetch_icon(client, base_url, folder, id) {
+            eprintln!("Cannot get icon {}: {}", id, e);
+            failed.push(format!("{} ({})", id, e));
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::IconError(failed.join(", ")))
+    }
+}
+
+fn fetch_icon(client: &Client, base_url: &str, folder: &Path, id: u64) -> Result<()> {
+    // downoad the image
+    let svg = client.get(&format!("{}{}{}.svg", base_url, ICON_PATH, id), None)?;
+    write_atomic(&folder.join(format!("{}.svg", id)), &svg)?;
+    convert_icon(&svg, folder, &id.to_string())
+}
+
+/// Convert the svg to a pdf and a png file
+fn convert_icon(svg: &[u8], folder: &Path, stem: &str) -> Result<()> {
+    let mut tree = usvg::Tree::from_data(svg, &usvg::Options::default())
+        .map_err(|e| Error::IconError(format!("invalid svg: {}", e)))?;
+    // compute the bounding boxes, without them nothing is rendered
+    tree.postprocess(
+        usvg::PostProcessingSteps::default(),
+        &usvg::fontdb::Database::new(),
+    );
+
+    // pdf
+    let pdf = svg2pdf::convert_tree(&tree, svg2pdf::Options::default());
+    write_atomic(&folder.join(format!("{}.pdf", stem)), &pdf)?;
+
+    // png
+    let scale = PNG_WIDTH as f32 / tree.size.width();
+    let height = (tree.size.height() * scale).ceil() as u32;
+    let mut pixmap = match tiny_skia::Pixmap::new(PNG_WIDTH, height) {
+        Some(p) => p,
+        None => return Err(Error::IconError("invalid svg size".to_string())),
+    };
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+    let png = pixmap
+        .encode_png()
+        .map_err(|e| Error::IconError(format!("cannot encode png: {}", e)))?;
+    write_atomic(&folder.join(format!("{}.png", stem)), &png)?;
+
+    Ok(())
+}
+
+/// Check that both the pdf and png of the icon exist, and are not truncated.
+fn is_icon_valid(folder: &Path, stem: &str) -> bool {
+    let pdf = fs::read(folder.join(format!("{}.pdf", stem))).unwrap_or_default();
+    let png = fs::read(folder.join(format!("{}.png", stem))).unwrap_or_default();
+    let pdf_valid =
+        pdf.starts_with(b"%PDF-") && pdf.windows(5).rev().take(32).any(|w| w == b"%%EOF");
+    let png_valid = png.starts_with(PNG_SIGNATURE) && png.ends_with(b"IEND\xaeB`\x82");
+    pdf_valid && png_valid
+}
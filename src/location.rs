@@ -0,0 +1,273 @@
+/*
+ * Meteoschweiz: Render meteo data from meteoschweiz.admin.ch
+ * Copyright (C) 2020  Tibor Schneider
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::settings::NamedLocation;
+use crate::{Error, Result};
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, IsTerminal, Write};
+
+lazy_static! {
+    static ref LOCALITIES: Vec<Locality> = {
+        std::str::from_utf8(include_bytes!("plz.csv"))
+            .unwrap()
+            .lines()
+            .filter(|l| !l.is_empty())
+            .map(|l| {
+                let mut fields = l.split(';');
+                Locality {
+                    plz: fields.next().unwrap().parse().unwrap(),
+                    name: fields.next().unwrap().to_string(),
+                    canton: fields.next().unwrap().to_string(),
+                }
+            })
+            .collect()
+    };
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Locality {
+    pub plz: u32,
+    pub name: String,
+    pub canton: String,
+}
+
+/// Select the location to show. `query` is either the name of a configured location, or any
+/// location accepted by `resolve`. Without a query, `fallback` or the first configured location
+/// is used.
+pub fn select(
+    query: Option<&str>,
+    named: &[NamedLocation],
+    fallback: Option<&str>,
+    default_plz: u32,
+) -> Result<Locality> {
+    if let Some(q) = query {
+        match named.iter().find(|n| n.name == q) {
+            Some(n) => resolve(Some(&n.location), default_plz),
+            None => resolve(Some(q), default_plz),
+        }
+    } else if fallback.is_some() {
+        resolve(fallback, default_plz)
+    } else if let Some(n) = named.first() {
+        resolve(Some(&n.location), default_plz)
+    } else {
+        lookup_plz(default_plz)
+    }
+}
+
+/// Resolve the location given by the user. The location can either be a PLZ, or the name of a
+/// locality. If no location is given, `default_plz` is used.
+pub fn resolve(location: Option<&str>, default_plz: u32) -> Result<Locality> {
+    let location = match location {
+        Some(l) => l.trim(),
+        None => return lookup_plz(default_plz),
+    };
+    if let Ok(plz) = location.parse::<u32>() {
+        return lookup_plz(plz);
+    }
+
+    let mut candidates = find_by_name(location);
+    // a locality with multiple PLZ is not ambiguous, just take the first one
+    candidates.dedup_by(|a, b| a.name == b.name && a.canton == b.canton);
+    match candidates.len() {
+        0 => Err(Error::LocationError(format!(
+            "Unknown locality: {} (try its PLZ instead)",
+            location
+        ))),
+        1 => Ok(candidates.remove(0)),
+        _ => choose_candidate(location, candidates),
+    }
+}
+
+/// Lookup a PLZ in the database
+pub fn lookup_plz(plz: u32) -> Result<Locality> {
+    match LOCALITIES.iter().find(|l| l.plz == plz) {
+        Some(l) => Ok(l.clone()),
+        None => Err(Error::LocationError(format!("Unknown PLZ: {}", plz))),
+    }
+}
+
+fn find_by_name(name: &str) -> Vec<Locality> {
+    let query = normalize(name);
+
+    // exact match
+    let exact: Vec<Locality> = LOCALITIES
+        .iter()
+        .filter(|l| normalize(&l.name) == query)
+        .cloned()
+        .collect();
+    if !exact.is_empty() {
+        return exact;
+    }
+
+    // match the beginning of the name or any part of it (like "Biel" for "Biel/Bienne")
+    let prefix: Vec<Locality> = LOCALITIES
+        .iter()
+        .filter(|l| {
+            let n = normalize(&l.name);
+            n.starts_with(&query) || n.split(' ').any(|part| part == query)
+        })
+        .cloned()
+        .collect();
+    if !prefix.is_empty() {
+        return prefix;
+    }
+
+    // allow some typos
+    let max_dist = std::cmp::max(1, query.chars().count() / 4);
+    let mut fuzzy: Vec<(usize, &Locality)> = LOCALITIES
+        .iter()
+        .map(|l| (levenshtein(&normalize(&l.name), &query), l))
+        .filter(|(d, _)| *d <= max_dist)
+        .collect();
+    fuzzy.sort_by_key(|(d, _)| *d);
+    match fuzzy.first() {
+        Some((best, _)) => {
+            let best = *best;
+            fuzzy
+                .into_iter()
+                .filter(|(d, _)| *d == best)
+                .map(|(_, l)| l.clone())
+                .collect()
+        }
+        None => Vec::new(),
+    }
+}
+
+fn choose_candidate(query: &str, candidates: Vec<Locality>) -> Result<Locality> {
+    let list = candidates
+        .iter()
+        .map(|l| format!("{} {} ({})", l.plz, l.name, l.canton))
+        .collect::<Vec<String>>();
+
+    // only ask if someone can answer
+    if !io::stdin().is_terminal() {
+        return Err(Error::LocationError(format!(
+            "Locality \"{}\" is ambiguous: {}",
+            query,
+            list.join(", ")
+        )));
+    }
+
+    println!("Locality \"{}\" is ambiguous:", query);
+    for (i, l) in list.iter().enumerate() {
+        println!("  [{}] {}", i + 1, l);
+    }
+    print!("Choose [1-{}]: ", list.len());
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    match answer.trim().parse::<usize>() {
+        Ok(i) if i >= 1 && i <= candidates.len() => Ok(candidates[i - 1].clone()),
+        _ => Err(Error::LocationError(format!(
+            "Invalid choice: {}",
+            answer.trim()
+        ))),
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.chars()
+        .flat_map(|c| c.to_lowercase())
+        .map(|c| match c {
+            'ä' | 'à' | 'â' | 'á' => 'a',
+            'ö' | 'ô' | 'ó' | 'ò' => 'o',
+            'ü' | 'û' | 'ù' | 'ú' => 'u',
+            'é' | 'è' | 'ê' | 'ë' => 'e',
+            'î' | 'ï' | 'í' | 'ì' => 'i',
+            'ç' => 'c',
+            '-' | '/' | '\'' => ' ',
+            c => c,
+        })
+        .filter(|c| *c != '.')
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut last = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let old = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                last
+            } else {
+                1 + std::cmp::min(last, std::cmp::min(row[j], row[j + 1]))
+            };
+            last = old;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plz(localities: &[Locality]) -> Vec<u32> {
+        localities.iter().map(|l| l.plz).collect()
+    }
+
+    #[test]
+    fn levenshtein_distance() {
+        assert_eq!(levenshtein("bern", "bern"), 0);
+        assert_eq!(levenshtein("", "thun"), 4);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("zurich", "zuerich"), 1);
+    }
+
+    #[test]
+    fn find_exact_name() {
+        let found = find_by_name("ZÜRICH");
+        assert!(!found.is_empty());
+        assert!(found.iter().all(|l| l.name == "Zürich"));
+        assert!(plz(&found).contains(&8001));
+        assert_eq!(plz(&find_by_name("st gallen"))[0], 9000);
+    }
+
+    #[test]
+    fn find_prefix_and_part_of_name() {
+        assert!(find_by_name("biel").iter().all(|l| l.name == "Biel/Bienne"));
+        assert!(find_by_name("Bienne")
+            .iter()
+            .all(|l| l.name == "Biel/Bienne"));
+        assert_eq!(plz(&find_by_name("Wallis")), vec![8304]);
+    }
+
+    #[test]
+    fn find_with_typos() {
+        assert_eq!(plz(&find_by_name("Winterthr"))[0], 8400);
+        assert!(find_by_name("Xylophon").is_empty());
+    }
+
+    #[test]
+    fn resolve_rejects_unknown_plz() {
+        assert_eq!(resolve(Some("8047"), 8001).unwrap().name, "Zürich");
+        assert!(matches!(
+            resolve(Some("9999"), 8001),
+            Err(Error::LocationError(_))
+        ));
+        assert_eq!(resolve(None, 3011).unwrap().name, "Bern");
+    }
+}
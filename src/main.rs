@@ -17,23 +17,33 @@
  */
 
 mod cache;
+mod discovery;
 mod errors;
 mod forecast;
+mod http;
+mod icons;
+mod location;
+mod render;
 mod settings;
+mod symbol;
+mod theme;
 mod utils;
+mod wind;
 
+use cache::View;
 use settings::SETTINGS as CFG;
 
-use std::env;
 use std::fs;
 use std::path::PathBuf;
-use std::process::{Command, Stdio};
+use std::process::Command;
 
-use clap::{App, Arg};
-use tera;
+use clap::{App, Arg, ArgMatches, SubCommand};
 
 pub use errors::{Error, Result};
 
+/// Exit code when an outdated forecast from the cache was shown
+const EXIT_STALE: i32 = 2;
+
 fn main() -> Result<()> {
     let matches = App::new("Meteo Schweiz")
         .version("0.1")
@@ -55,168 +65,476 @@ fn main() -> Result<()> {
                 .takes_value(false)
                 .help("Show all 7 days"),
         )
+        .arg(
+            Arg::with_name("base_url")
+                .long("base-url")
+                .value_name("URL")
+                .takes_value(true)
+                .help("Base URL of meteoschweiz.admin.ch (overrides the config)"),
+        )
+        .arg(
+            Arg::with_name("location")
+                .long("location")
+                .value_name("LOCATION")
+                .takes_value(true)
+                .help("Name of a configured location, or PLZ or name of the locality"),
+        )
+        .arg(
+            Arg::with_name("all")
+                .short("a")
+                .long("all")
+                .takes_value(false)
+                .help("Update the forecast of all configured locations"),
+        )
+        .arg(
+            Arg::with_name("offline")
+                .long("offline")
+                .takes_value(false)
+                .help("Do not connect to meteoschweiz.admin.ch, use the cached forecast"),
+        )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .value_name("DIR")
+                .takes_value(true)
+                .conflicts_with("replay")
+                .help("Store all responses from meteoschweiz.admin.ch in DIR"),
+        )
+        .arg(
+            Arg::with_name("replay")
+                .long("replay")
+                .value_name("DIR")
+                .takes_value(true)
+                .help("Serve all responses from DIR, recorded with --record"),
+        )
+        .arg(
+            Arg::with_name("lenient")
+                .long("lenient")
+                .takes_value(false)
+                .help("Skip invalid data points in the forecast instead of failing"),
+        )
+        .arg(
+            Arg::with_name("force_render")
+                .long("force-render")
+                .takes_value(false)
+                .help("Render the pdf even if it is cached"),
+        )
+        .subcommand(
+            SubCommand::with_name("cache")
+                .about("Inspect and manage the cache")
+                .subcommand(SubCommand::with_name("list").about("List all cached locations"))
+                .subcommand(
+                    SubCommand::with_name("info")
+                        .about("Show all cached files")
+                        .arg(
+                            Arg::with_name("location")
+                                .value_name("LOCATION")
+                                .help("Only show this location (PLZ or name)"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("prune")
+                        .about("Remove old locations and http responses from the cache")
+                        .arg(
+                            Arg::with_name("older_than")
+                                .long("older-than")
+                                .value_name("DAYS")
+                                .takes_value(true)
+                                .help(
+                                    "Remove locations and http responses not updated in the \
+                                     last DAYS days",
+                                ),
+                        )
+                        .arg(
+                            Arg::with_name("keep")
+                                .long("keep")
+                                .value_name("N")
+                                .takes_value(true)
+                                .help("Only keep the N most recently updated locations"),
+                        ),
+                )
+                .subcommand(SubCommand::with_name("clear").about("Remove everything")),
+        )
+        .subcommand(
+            SubCommand::with_name("icons")
+                .about("Manage the weather icons")
+                .subcommand(
+                    SubCommand::with_name("update")
+                        .about("Download fresh icons from meteoschweiz.admin.ch"),
+                ),
+        )
         .get_matches();
 
-    let day_idx: usize = matches.value_of("day").unwrap_or("0").parse().unwrap();
-    let show_long: bool = matches.is_present("long");
+    if let Some(cache_matches) = matches.subcommand_matches("cache") {
+        return cache_command(cache_matches);
+    }
+    if let Some(icons_matches) = matches.subcommand_matches("icons") {
+        return icons_command(&matches, icons_matches);
+    }
 
-    // download all missing icons
-    utils::fetch_icons(&CFG.icon_path)?;
+    let day_idx: usize = parse_arg(matches.value_of("day").unwrap_or("0"), "--day")?;
+    let view = match matches.is_present("long") {
+        true => View::Long,
+        false => View::Day(day_idx),
+    };
+    let force_render: bool = matches.is_present("force_render");
+    let lenient: bool = matches.is_present("lenient") || CFG.lenient;
+    let base_url = base_url(&matches);
+
+    // resolve and validate the location before doing any network request
+    let location = location::select(
+        matches.value_of("location"),
+        &CFG.locations,
+        CFG.location.as_deref(),
+        CFG.location_plz,
+    )?;
+    println!("Location: {} {}", location.plz, location.name);
+    let timezone: chrono_tz::Tz = parse_arg(&CFG.timezone, "timezone")?;
+    let theme = theme::Theme::load(&CFG.icon_path, &CFG.icon_theme)?;
 
     // generate template if it does not yet exist
     utils::generate_template(&CFG.template_file, &CFG.template_long_file)?;
 
-    // check last cached json name
-    println!("Extracting json url...");
-    let cached_json_url = cache::check_cache(&CFG.cache_folder)?;
-    let new_json_url = forecast::get_forecast_chart_json_url(CFG.location_plz)?;
-
-    if cached_json_url.unwrap_or("".to_string()) == new_json_url {
-        if !cache::is_pdf_cached(&CFG.cache_folder, day_idx, show_long) {
-            // get the forecast
-            let fc = match cache::get_cached_forecast(&CFG.cache_folder) {
-                Ok(fc) => {
-                    println!("Using cached forecast...");
-                    fc
-                }
-                Err(e) => {
+    // make sure that no other instance modifies the cache while we are using it
+    let cache_lock = cache::lock(&CFG.cache_folder)?;
+
+    // all locations that should be updated in this run
+    let mut targets: Vec<location::Locality> = vec![location.clone()];
+    if matches.is_present("all") {
+        for named in CFG.locations.iter() {
+            match location::resolve(Some(&named.location), CFG.location_plz) {
+                Ok(l) if !targets.contains(&l) => targets.push(l),
+                Ok(_) => {}
+                Err(e) => eprintln!("Skipping location \"{}\": {}", named.name, e),
+            }
+        }
+    }
+
+    let client = http::Client::new(&CFG, http_mode(&matches))?;
+    let mut offline: bool = matches.is_present("offline");
+    if !offline {
+        // every location is updated on its own, such that one failure does not affect the others
+        for target in targets.iter() {
+            match update_forecast(&client, base_url, target, lenient) {
+                Ok(()) => {}
+                // fall back to the cached forecast if meteoschweiz.admin.ch cannot be reached,
+                // and do not try the other locations either
+                Err(e) if target == &location && e.is_transport() => {
                     eprintln!(
-                        "Cannot read forecast from cache: {:?}\nPulling from meteoschweiz.ch...",
+                        "Cannot update the forecast: {}\nUsing the cached forecast...",
                         e
                     );
-                    // fetch it
-                    let fc = forecast::fetch_forecast(&new_json_url, &CFG.icon_path)?;
-                    // serialize the forecast to a json file in the cache for future access
-                    cache::cache_forecast(&CFG.cache_folder, &fc)?;
-                    fc
+                    offline = true;
+                    break;
                 }
-            };
-            // generate the latex file and run pdflatex
-            if show_long {
-                apply_template_long(&fc, &CFG.template_long_file, &CFG.cache_folder)?;
-            } else {
-                apply_template(&fc[day_idx], &CFG.template_file, &CFG.cache_folder, day_idx)?;
+                Err(e) if target == &location => return Err(e),
+                Err(e) => eprintln!("Cannot update the forecast of {}: {}", target.name, e),
             }
         }
+    }
+
+    let cache_folder = cache::location_folder(&CFG.cache_folder, location.plz);
+    let json_url = cache::check_cache(&cache_folder)?.unwrap_or_default();
+
+    // get the forecast
+    let (mut fc, created) = match cache::get_cached_forecast(&cache_folder)? {
+        Some(cached) => {
+            println!("Using cached forecast...");
+            (cached.forecast, cached.created)
+        }
+        None if offline => return Err(Error::NoCachedForecast),
+        None => {
+            println!("No usable forecast in the cache, pulling from meteoschweiz.ch...");
+            // fetch it
+            let fc = forecast::fetch_forecast(&client, base_url, &json_url, &location, lenient)?;
+            // serialize the forecast to a json file in the cache for future access
+            cache::cache_forecast(&cache_folder, &fc)?;
+            cache::record_artifact(
+                &cache_folder,
+                cache::FORECAST_ARTIFACT,
+                location.plz,
+                &json_url,
+                None,
+            )?;
+            (fc, chrono::Utc::now().timestamp())
+        }
+    };
+
+    // install the icons of all weather symbols in the forecast, and download unknown ones
+    let ids = forecast::symbol_ids(&fc);
+    let online = !offline && theme.name == theme::DEFAULT_THEME;
+    // do not mix the bundled substitutes into a folder that already holds the original icons
+    let prefer_download = online && icons::holds_originals(&theme);
+    let mut missing = icons::install_icons(&theme, &ids, !prefer_download)?;
+    if prefer_download && !missing.is_empty() {
+        if let Err(e) = icons::fetch_icons(&client, base_url, &theme.folder, &missing) {
+            eprintln!("Cannot download the icons, using the bundled ones: {}", e);
+        }
+        missing = icons::install_icons(&theme, &ids, true)?;
+    }
+    if online && !prefer_download {
+        icons::fetch_icons(&client, base_url, &theme.folder, &missing)?;
     } else {
-        // clear the cache
-        cache::clear_cache(&CFG.cache_folder)?;
-        // update the json url file
-        cache::set_current_json_url(&CFG.cache_folder, &new_json_url)?;
-        // fetch and parse the forecast
-        println!("Fetching new forecast...");
-        let fc = forecast::fetch_forecast(&new_json_url, &CFG.icon_path)?;
-        // serialize the forecast to a json file in the cache for future access
-        cache::cache_forecast(&CFG.cache_folder, &fc)?;
-        // generate the latex file and run pdflatex
-        if show_long {
-            apply_template_long(&fc, &CFG.template_long_file, &CFG.cache_folder)?;
-        } else {
-            apply_template(&fc[day_idx], &CFG.template_file, &CFG.cache_folder, day_idx)?;
+        for id in missing {
+            eprintln!("Icon {} is missing in the theme \"{}\"", id, theme.name);
         }
     }
+    forecast::localize(&mut fc, timezone);
+    forecast::apply_theme(&mut fc, &theme);
+
+    // generate the context, and show the age of the data when offline
+    let data_age = chrono::Utc::now().timestamp() - created;
+    let stale = offline && data_age > CFG.stale_after_hours * 3600;
+    let mut ctx = render::context(&fc, view, &location, &theme)?;
+    ctx.insert("offline", &offline);
+    if offline {
+        println!("Offline, data from {} ago", utils::format_age(data_age));
+        ctx.insert("data_age", &utils::format_age(data_age));
+        ctx.insert("stale", &stale);
+    }
+
+    // read the template and check if the pdf was rendered with exactly the same input
+    let template = fs::read_to_string(match view {
+        View::Long => &CFG.template_long_file,
+        View::Day(_) => &CFG.template_file,
+    })?;
+    let render_hash = render::render_hash(&template, &ctx, view, &[&CFG.icon_path]);
+    if force_render
+        || !cache::is_pdf_cached(&cache_folder, view, location.plz, &json_url, render_hash)?
+    {
+        // generate the latex file and run pdflatex
+        render::render(&ctx, view, &template, &cache_folder)?;
+        cache::record_artifact(
+            &cache_folder,
+            &view.pdf(),
+            location.plz,
+            &json_url,
+            Some(render_hash),
+        )?;
+    }
 
     // display the requested forecast
-    show_forecast(&CFG.cache_folder, day_idx, show_long)?;
+    drop(cache_lock);
+    show_forecast(&cache_folder, view)?;
+
+    if stale {
+        eprintln!(
+            "The forecast is older than {} hours!",
+            CFG.stale_after_hours
+        );
+        std::process::exit(EXIT_STALE);
+    }
 
     Ok(())
 }
 
-fn apply_template_long(
-    fc: &forecast::Forecast,
-    template_filename: &str,
-    cache_folder: &str,
+/// Fetch and cache the forecast of the location, if a newer one is available
+fn update_forecast(
+    client: &http::Client,
+    base_url: &str,
+    target: &location::Locality,
+    lenient: bool,
 ) -> Result<()> {
-    println!("Generating forecast pdf...");
-
-    // read template file
-    let template = fs::read_to_string(template_filename)?;
-
-    // prepare target tex filename
-    let mut target_file = PathBuf::from(&cache_folder);
-    target_file.push("long.tex");
-
-    // generate context and tex file
-    let fc_long = forecast::ForecastLong::from(fc);
-    let mut ctx = tera::Context::new();
-    ctx.insert("forecast_long", &fc_long);
-    let tex_file = tera::Tera::one_off(&template, &ctx, false)?;
-
-    // write back
-    fs::write(&target_file, &tex_file)?;
-
-    // change directory into cache folder
-    let old_dir = env::current_dir()?;
-    env::set_current_dir(&cache_folder)?;
-
-    // compile pdflatex
-    let status = Command::new("pdflatex")
-        .arg(&target_file)
-        .arg("--output-format=pdf")
-        .stdout(Stdio::piped())
-        .stdin(Stdio::piped())
-        .status()?;
-
-    env::set_current_dir(old_dir)?;
+    // check last cached json name of the location
+    println!("Extracting json url for {}...", target.name);
+    let target_cache = cache::location_folder(&CFG.cache_folder, target.plz);
+    let cached_json_url = cache::check_cache(&target_cache)?;
+    let discovered = discovery::discover_json_url(
+        client,
+        base_url,
+        target.plz,
+        &CFG.discovery,
+        CFG.json_url_template.as_deref(),
+    )?;
+    println!(
+        "Found json url for {} using strategy \"{}\"",
+        target.name, discovered.strategy
+    );
+    if cached_json_url.as_deref() == Some(discovered.json_url.as_str()) {
+        return Ok(());
+    }
 
-    if !status.success() {
-        Err(Error::PdflatexError(status.code()))
-    } else {
-        Ok(())
+    println!("Fetching new forecast...");
+    let json_url = discovered.json_url;
+    let fc = forecast::fetch_forecast(client, base_url, &json_url, target, lenient)?;
+    // clear the cache, including the http cache entry of the old forecast
+    cache::clear_cache(&target_cache)?;
+    if let Some(old_url) = cached_json_url {
+        http::forget_cached(&CFG.cache_folder, &old_url)?;
     }
+    // update the json url file
+    cache::set_current_json_url(&target_cache, &json_url)?;
+    // serialize the forecast to a json file in the cache for future access
+    cache::cache_forecast(&target_cache, &fc)?;
+    cache::record_artifact(
+        &target_cache,
+        cache::FORECAST_ARTIFACT,
+        target.plz,
+        &json_url,
+        None,
+    )?;
+    Ok(())
 }
 
-fn apply_template(
-    day: &forecast::ForecastDay,
-    template_filename: &str,
-    cache_folder: &str,
-    forecast_idx: usize,
-) -> Result<()> {
-    println!("Generating forecast pdf...");
-
-    // read template file
-    let template = fs::read_to_string(template_filename)?;
-
-    // prepare target tex filename
-    let mut target_file = PathBuf::from(&cache_folder);
-    target_file.push(format!("{}.tex", forecast_idx));
-
-    // generate context and tex file
-    let mut ctx = tera::Context::new();
-    ctx.insert("forecast_day", day);
-    let tex_file = tera::Tera::one_off(&template, &ctx, false)?;
-
-    // write back
-    fs::write(&target_file, &tex_file)?;
-
-    // change directory into cache folder
-    let old_dir = env::current_dir()?;
-    env::set_current_dir(&cache_folder)?;
+fn show_forecast(cache_folder: &str, view: View) -> Result<()> {
+    Command::new(&CFG.pdf_viewer)
+        .args(&CFG.pdf_viewer_args)
+        .arg(format!("{}/{}.pdf", cache_folder, view.name()))
+        .output()?;
+    Ok(())
+}
 
-    // compile pdflatex
-    let status = Command::new("pdflatex")
-        .arg(&target_file)
-        .arg("--output-format=pdf")
-        .stdout(Stdio::piped())
-        .stdin(Stdio::piped())
-        .status()?;
+/// Base URL from the command line, or from the config
+fn base_url<'a>(matches: &'a ArgMatches) -> &'a str {
+    matches
+        .value_of("base_url")
+        .map(|u| u.trim_end_matches('/'))
+        .unwrap_or(&CFG.base_url)
+}
 
-    env::set_current_dir(old_dir)?;
+fn http_mode(matches: &ArgMatches) -> http::Mode {
+    match (matches.value_of("record"), matches.value_of("replay")) {
+        (Some(dir), _) => http::Mode::Record(PathBuf::from(dir)),
+        (None, Some(dir)) => http::Mode::Replay(PathBuf::from(dir)),
+        (None, None) => http::Mode::Live,
+    }
+}
 
-    if !status.success() {
-        Err(Error::PdflatexError(status.code()))
-    } else {
-        Ok(())
+fn icons_command(matches: &ArgMatches, icons_matches: &ArgMatches) -> Result<()> {
+    let _cache_lock = cache::lock(&CFG.cache_folder)?;
+    let base_url = base_url(matches);
+    let client = http::Client::new(&CFG, http_mode(matches))?;
+    match icons_matches.subcommand() {
+        ("update", Some(_)) => icons::update_icons(&client, base_url, &CFG.icon_path),
+        _ => {
+            println!("{}", icons_matches.usage());
+            Ok(())
+        }
     }
 }
 
-fn show_forecast(cache_folder: &str, forecast_idx: usize, show_long: bool) -> Result<()> {
-    Command::new(&CFG.pdf_viewer)
-        .args(&CFG.pdf_viewer_args)
-        .arg(match show_long {
-            false => format!("{}/{}.pdf", cache_folder, forecast_idx),
-            true => format!("{}/long.pdf", cache_folder),
-        })
-        .output()?;
+fn cache_command(matches: &ArgMatches) -> Result<()> {
+    let _cache_lock = cache::lock(&CFG.cache_folder)?;
+    let now = chrono::Utc::now().timestamp();
+    match matches.subcommand() {
+        ("info", Some(sub)) => {
+            let plz = match sub.value_of("location") {
+                Some(l) => Some(location::resolve(Some(l), CFG.location_plz)?.plz),
+                None => None,
+            };
+            for l in cache::list_cache(&CFG.cache_folder)? {
+                if plz.is_some() && plz != Some(l.plz) {
+                    continue;
+                }
+                println!("{} {}", l.plz, locality_name(l.plz));
+                println!("  folder:   {}", l.folder.display());
+                if let Some(fc) = l.forecast.as_ref() {
+                    println!("  version:  {}", fc.version);
+                    println!("  json url: {}", fc.json_url);
+                    println!("  fetched:  {} ago", utils::format_age(now - fc.created));
+                }
+                println!("  size:     {}", utils::format_size(l.size()));
+                for f in l.files.iter() {
+                    let origin = match f.manifest.as_ref() {
+                        Some(m)
+                            if l.forecast.as_ref().map(|fc| &fc.version) == Some(&m.version) =>
+                        {
+                            ""
+                        }
+                        Some(_) => " (outdated)",
+                        None => "",
+                    };
+                    println!(
+                        "    {:<20} {:>10} {:>12} ago{}",
+                        f.name,
+                        utils::format_size(f.size),
+                        utils::format_age(now - f.modified),
+                        origin
+                    );
+                }
+            }
+            let http_files = cache::list_http_cache(&CFG.cache_folder)?;
+            if plz.is_none() && !http_files.is_empty() {
+                println!("http cache");
+                println!(
+                    "  size:     {}",
+                    utils::format_size(http_files.iter().map(|f| f.size).sum())
+                );
+                for f in http_files.iter() {
+                    println!(
+                        "    {:<20} {:>10} {:>12} ago",
+                        f.name,
+                        utils::format_size(f.size),
+                        utils::format_age(now - f.modified)
+                    );
+                }
+            }
+        }
+        ("prune", Some(sub)) => {
+            let max_age = match sub.value_of("older_than") {
+                Some(days) => Some(parse_arg::<i64>(days, "--older-than")? * 24 * 3600),
+                None => None,
+            };
+            let keep = match sub.value_of("keep") {
+                Some(n) => Some(parse_arg::<usize>(n, "--keep")?),
+                None => None,
+            };
+            for l in cache::prune_cache(&CFG.cache_folder, max_age, keep)? {
+                println!("Removed {} {}", l.plz, locality_name(l.plz));
+            }
+            if let Some(max_age) = max_age {
+                for f in cache::prune_http_cache(&CFG.cache_folder, max_age)? {
+                    println!("Removed http cache entry {}", f.name);
+                }
+            }
+        }
+        ("clear", Some(_)) => {
+            cache::wipe_cache(&CFG.cache_folder)?;
+            println!("Cache cleared");
+        }
+        _ => {
+            println!(
+                "{:<6}{:<24}{:<28}{:>14}{:>12}",
+                "PLZ", "Locality", "Version", "Age", "Size"
+            );
+            for l in cache::list_cache(&CFG.cache_folder)? {
+                let (version, age) = match l.forecast.as_ref() {
+                    Some(fc) => (fc.version.clone(), utils::format_age(now - fc.created)),
+                    None => ("-".to_string(), "-".to_string()),
+                };
+                println!(
+                    "{:<6}{:<24}{:<28}{:>14}{:>12}",
+                    l.plz,
+                    locality_name(l.plz),
+                    version,
+                    age,
+                    utils::format_size(l.size())
+                );
+            }
+            let http_files = cache::list_http_cache(&CFG.cache_folder)?;
+            if !http_files.is_empty() {
+                println!(
+                    "{:<6}{:<24}{:<28}{:>14}{:>12}",
+                    "-",
+                    "http cache",
+                    format!("{} files", http_files.len()),
+                    "-",
+                    utils::format_size(http_files.iter().map(|f| f.size).sum())
+                );
+            }
+        }
+    }
     Ok(())
 }
+
+fn locality_name(plz: u32) -> String {
+    location::lookup_plz(plz)
+        .map(|l| l.name)
+        .unwrap_or_else(|_| "?".to_string())
+}
+
+fn parse_arg<T: std::str::FromStr>(value: &str, name: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| Error::ArgumentError(format!("Invalid value for {}: {}", name, value)))
+}
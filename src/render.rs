@@ -0,0 +1,121 @@
+/*
+ * Meteoschweiz: Render meteo data from meteoschweiz.admin.ch
+ * Copyright (C) 2020  Tibor Schneider
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::cache::View;
+use crate::forecast::{Forecast, ForecastLong};
+use crate::location::Locality;
+use crate::theme::Theme;
+use crate::utils::{write_atomic, Fnv64};
+use crate::{Error, Result};
+
+use std::fs;
+use std::hash::Hasher;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Generate the context for the template. Fails if the day is not part of the forecast.
+pub fn context(
+    fc: &Forecast,
+    view: View,
+    location: &Locality,
+    theme: &Theme,
+) -> Result<tera::Context> {
+    let mut ctx = tera::Context::new();
+    match view {
+        View::Day(idx) => match fc.days.get(idx) {
+            Some(day) => ctx.insert("forecast_day", day),
+            None => {
+                return Err(Error::ArgumentError(format!(
+                    "Invalid day {}, the forecast has {} days (0 to {})",
+                    idx,
+                    fc.days.len(),
+                    fc.days.len().saturating_sub(1)
+                )))
+            }
+        },
+        View::Long => ctx.insert("forecast_long", &ForecastLong::from(fc)),
+    }
+    ctx.insert("meta", &fc.meta);
+    ctx.insert("location", location);
+    ctx.insert("icon_theme", theme);
+    Ok(ctx)
+}
+
+/// Hash of everything that goes into the rendered pdf. If any of it changes, the pdf needs to be
+/// rendered again.
+pub fn render_hash(template: &str, ctx: &tera::Context, view: View, settings: &[&str]) -> u64 {
+    let mut hasher = Fnv64::default();
+    hasher.write(template.as_bytes());
+    hasher.write(view.name().as_bytes());
+    for setting in settings {
+        hasher.write(setting.as_bytes());
+    }
+    hasher.write(ctx.clone().into_json().to_string().as_bytes());
+    hasher.finish()
+}
+
+pub fn render(ctx: &tera::Context, view: View, template: &str, cache_folder: &str) -> Result<()> {
+    println!("Generating forecast pdf...");
+
+    // prepare target tex filename
+    let mut target_file = PathBuf::from(&cache_folder);
+    target_file.push(format!("{}.tex", view.name()));
+
+    // generate tex file
+    let tex_file = tera::Tera::one_off(template, ctx, false)?;
+
+    // write back
+    write_atomic(&target_file, tex_file.as_bytes())?;
+
+    // compile in a private directory, such that other instances never see a half-written pdf
+    let mut build_dir = PathBuf::from(&cache_folder);
+    build_dir.push(format!(".render-{}", std::process::id()));
+    fs::create_dir_all(&build_dir)?;
+
+    // compile pdflatex
+    let status = Command::new("pdflatex")
+        .arg(&target_file)
+        .arg("--output-format=pdf")
+        .arg(format!("-output-directory={}", build_dir.display()))
+        .stdout(Stdio::piped())
+        .stdin(Stdio::piped())
+        .status();
+
+    let result = match status {
+        Ok(status) if status.success() => {
+            let mut target_pdf = PathBuf::from(&cache_folder);
+            target_pdf.push(view.pdf());
+            fs::rename(build_dir.join(view.pdf()), target_pdf).map_err(Error::from)
+        }
+        Ok(status) => {
+            // keep the log next to the tex file, to debug the template
+            let log_file = target_file.with_extension("log");
+            let built_log = build_dir.join(log_file.file_name().unwrap_or_default());
+            if fs::rename(built_log, &log_file).is_ok() {
+                eprintln!("pdflatex failed, see {}", log_file.display());
+            }
+            Err(Error::PdflatexError(status.code()))
+        }
+        Err(e) => Err(e.into()),
+    };
+    // remove the build directory in any case, but report the error of the compilation first
+    let cleanup = fs::remove_dir_all(build_dir);
+    result?;
+    cleanup?;
+    Ok(())
+}
@@ -20,7 +20,6 @@ use config::{Config, File};
 use dirs::{cache_dir, config_dir};
 use lazy_static::lazy_static;
 use serde::Deserialize;
-use shellexpand;
 use std::fs;
 
 lazy_static! {
@@ -41,6 +40,12 @@ lazy_static! {
                 p.into_os_string().into_string().unwrap()
             })
             .unwrap()
+            .set_default("icon_theme", "default")
+            .unwrap()
+            .set_default("base_url", "https://www.meteoschweiz.admin.ch")
+            .unwrap()
+            .set_default("discovery", vec!["scrape", "versions", "template"])
+            .unwrap()
             .set_default("location_plz", 8001)
             .unwrap()
             .set_default("template_file", {
@@ -63,6 +68,23 @@ lazy_static! {
                 p.into_os_string().into_string().unwrap()
             })
             .unwrap()
+            .set_default("http_timeout", 30)
+            .unwrap()
+            .set_default("http_connect_timeout", 10)
+            .unwrap()
+            .set_default("http_retries", 3)
+            .unwrap()
+            .set_default(
+                "user_agent",
+                format!("meteoschweiz/{}", env!("CARGO_PKG_VERSION")),
+            )
+            .unwrap()
+            .set_default("stale_after_hours", 12)
+            .unwrap()
+            .set_default("lenient", false)
+            .unwrap()
+            .set_default("timezone", "Europe/Zurich")
+            .unwrap()
             .set_default("pdf_viewer", "zathura")
             .unwrap()
             .set_default("pdf_viewer_args", vec!["--fork"])
@@ -91,15 +113,39 @@ fn generate_missing_config_file() {
 
 #[derive(Debug, Deserialize)]
 pub struct Settings {
+    pub base_url: String,
+    pub discovery: Vec<String>,
+    #[serde(default)]
+    pub json_url_template: Option<String>,
     pub icon_path: String,
+    pub icon_theme: String,
+    #[serde(default)]
+    pub location: Option<String>,
     pub location_plz: u32,
+    #[serde(default)]
+    pub locations: Vec<NamedLocation>,
     pub template_file: String,
     pub template_long_file: String,
     pub cache_folder: String,
+    pub http_timeout: u64,
+    pub http_connect_timeout: u64,
+    pub http_retries: u32,
+    pub user_agent: String,
+    #[serde(default)]
+    pub proxy: Option<String>,
+    pub stale_after_hours: i64,
+    pub lenient: bool,
+    pub timezone: String,
     pub pdf_viewer: String,
     pub pdf_viewer_args: Vec<String>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct NamedLocation {
+    pub name: String,
+    pub location: String,
+}
+
 impl Settings {
     fn expand(mut self) -> Self {
         self.icon_path = shellexpand::full(&self.icon_path).unwrap().into_owned();
@@ -108,6 +154,7 @@ impl Settings {
             .unwrap()
             .into_owned();
         self.cache_folder = shellexpand::full(&self.cache_folder).unwrap().into_owned();
+        self.base_url = self.base_url.trim_end_matches('/').to_string();
         self
     }
 }
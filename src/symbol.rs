@@ -0,0 +1,366 @@
+/*
+ * Meteoschweiz: Render meteo data from meteoschweiz.admin.ch
+ * Copyright (C) 2020  Tibor Schneider
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
+
+/// Weather symbols of the night are offset by this value
+const NIGHT_OFFSET: u64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloudCover {
+    Clear,
+    MostlyClear,
+    PartlyCloudy,
+    MostlyCloudy,
+    Overcast,
+    HighClouds,
+    LowStratus,
+    Fog,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Precipitation {
+    Rain,
+    Drizzle,
+    Sleet,
+    Snow,
+    Hail,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Intensity {
+    Light,
+    Moderate,
+    Heavy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    De,
+    Fr,
+    It,
+    En,
+}
+
+/// Weather symbol of meteoschweiz, built from `weather_symbol_id`. Symbols 1 to 35 are used
+/// during the day, and 101 to 135 are the same symbols at night.
+///
+/// The cache only stores the ID, since everything else is derived from it. Templates get all
+/// fields, including the description in every language.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(from = "u64")]
+pub struct WeatherSymbol {
+    pub id: u64,
+    pub night: bool,
+    pub cloud_cover: CloudCover,
+    pub precipitation: Option<Precipitation>,
+    pub intensity: Option<Intensity>,
+    pub showers: bool,
+    pub thunder: bool,
+}
+
+impl From<u64> for WeatherSymbol {
+    fn from(id: u64) -> Self {
+        use CloudCover::*;
+        use Intensity::*;
+        use Precipitation::*;
+        let night = id > NIGHT_OFFSET;
+        let day_id = if night { id - NIGHT_OFFSET } else { id };
+        // (cloud cover, precipitation, intensity, showers, thunder)
+        let (cloud_cover, precipitation, intensity, showers, thunder) = match day_id {
+            1 => (Clear, None, None, false, false),
+            2 => (MostlyClear, None, None, false, false),
+            3 => (PartlyCloudy, None, None, false, false),
+            4 => (MostlyCloudy, None, None, false, false),
+            5 => (Overcast, None, None, false, false),
+            6 => (PartlyCloudy, Some(Rain), Some(Light), true, false),
+            7 => (PartlyCloudy, Some(Sleet), Some(Light), true, false),
+            8 => (PartlyCloudy, Some(Snow), Some(Light), true, false),
+            9 => (MostlyCloudy, Some(Rain), Some(Light), true, false),
+            10 => (MostlyCloudy, Some(Sleet), Some(Light), true, false),
+            11 => (MostlyCloudy, Some(Snow), Some(Light), true, false),
+            12 => (PartlyCloudy, None, None, true, true),
+            13 => (MostlyCloudy, None, None, true, true),
+            14 => (Overcast, Some(Rain), Some(Light), false, false),
+            15 => (Overcast, Some(Sleet), Some(Light), false, false),
+            16 => (Overcast, Some(Snow), Some(Light), false, false),
+            17 => (Overcast, Some(Rain), Some(Moderate), false, false),
+            18 => (Overcast, Some(Sleet), Some(Moderate), false, false),
+            19 => (Overcast, Some(Snow), Some(Moderate), false, false),
+            20 => (Overcast, Some(Rain), Some(Heavy), false, false),
+            21 => (Overcast, Some(Sleet), Some(Heavy), false, false),
+            22 => (Overcast, Some(Snow), Some(Heavy), false, false),
+            23 => (Overcast, Some(Rain), Some(Moderate), false, true),
+            24 => (Overcast, Some(Rain), Some(Heavy), false, true),
+            25 => (Overcast, Some(Hail), Some(Heavy), false, true),
+            26 => (HighClouds, None, None, false, false),
+            27 => (LowStratus, None, None, false, false),
+            28 => (Fog, None, None, false, false),
+            29 => (PartlyCloudy, Some(Rain), Some(Moderate), false, false),
+            30 => (PartlyCloudy, Some(Snow), Some(Moderate), false, false),
+            31 => (PartlyCloudy, Some(Sleet), Some(Moderate), false, false),
+            32 => (MostlyCloudy, Some(Drizzle), Some(Light), false, false),
+            33 => (MostlyCloudy, Some(Rain), Some(Light), false, false),
+            34 => (MostlyCloudy, Some(Snow), Some(Light), false, false),
+            35 => (MostlyCloudy, Some(Sleet), Some(Light), false, false),
+            // unknown symbols are treated as cloudy, without any precipitation
+            _ => (MostlyCloudy, None, None, false, false),
+        };
+        Self {
+            id,
+            night,
+            cloud_cover,
+            precipitation,
+            intensity,
+            showers,
+            thunder,
+        }
+    }
+}
+
+impl WeatherSymbol {
+    /// Human description of the symbol, like "partly cloudy, light snow showers"
+    pub fn description(&self, lang: Language) -> String {
+        let sky = sky_description(self.cloud_cover, self.night, lang);
+        let precipitation = self
+            .precipitation
+            .map(|p| precipitation_description(p, self.intensity, self.showers, lang));
+        let weather = match (self.thunder, precipitation) {
+            (false, None) => return sky.to_string(),
+            (false, Some(p)) => p,
+            (true, p) => thunder_description(p, lang),
+        };
+        format!("{}, {}", sky, weather)
+    }
+}
+
+impl Serialize for WeatherSymbol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return serializer.serialize_u64(self.id);
+        }
+        let mut s = serializer.serialize_struct("WeatherSymbol", 8)?;
+        s.serialize_field("id", &self.id)?;
+        s.serialize_field("night", &self.night)?;
+        s.serialize_field("cloud_cover", &self.cloud_cover)?;
+        s.serialize_field("precipitation", &self.precipitation)?;
+        s.serialize_field("intensity", &self.intensity)?;
+        s.serialize_field("showers", &self.showers)?;
+        s.serialize_field("thunder", &self.thunder)?;
+        s.serialize_field("description", &Descriptions::from(self))?;
+        s.end()
+    }
+}
+
+/// Description in all languages, for the templates
+#[derive(Serialize)]
+pub struct Descriptions {
+    de: String,
+    fr: String,
+    it: String,
+    en: String,
+}
+
+impl Descriptions {
+    pub fn new<F: Fn(Language) -> String>(description: F) -> Self {
+        Self {
+            de: description(Language::De),
+            fr: description(Language::Fr),
+            it: description(Language::It),
+            en: description(Language::En),
+        }
+    }
+}
+
+impl From<&WeatherSymbol> for Descriptions {
+    fn from(symbol: &WeatherSymbol) -> Self {
+        Self::new(|lang| symbol.description(lang))
+    }
+}
+
+fn sky_description(cover: CloudCover, night: bool, lang: Language) -> &'static str {
+    use CloudCover::*;
+    use Language::*;
+    match (cover, night, lang) {
+        (Clear, false, De) => "sonnig",
+        (Clear, false, Fr) => "ensoleillé",
+        (Clear, false, It) => "soleggiato",
+        (Clear, false, En) => "sunny",
+        (Clear, true, De) => "klar",
+        (Clear, true, Fr) => "dégagé",
+        (Clear, true, It) => "sereno",
+        (Clear, true, En) => "clear",
+        (MostlyClear, false, De) => "ziemlich sonnig",
+        (MostlyClear, false, Fr) => "assez ensoleillé",
+        (MostlyClear, false, It) => "abbastanza soleggiato",
+        (MostlyClear, false, En) => "mostly sunny",
+        (MostlyClear, true, De) => "ziemlich klar",
+        (MostlyClear, true, Fr) => "peu nuageux",
+        (MostlyClear, true, It) => "poco nuvoloso",
+        (MostlyClear, true, En) => "mostly clear",
+        (PartlyCloudy, _, De) => "wechselnd bewölkt",
+        (PartlyCloudy, _, Fr) => "partiellement nuageux",
+        (PartlyCloudy, _, It) => "parzialmente nuvoloso",
+        (PartlyCloudy, _, En) => "partly cloudy",
+        (MostlyCloudy, _, De) => "stark bewölkt",
+        (MostlyCloudy, _, Fr) => "très nuageux",
+        (MostlyCloudy, _, It) => "molto nuvoloso",
+        (MostlyCloudy, _, En) => "mostly cloudy",
+        (Overcast, _, De) => "bedeckt",
+        (Overcast, _, Fr) => "couvert",
+        (Overcast, _, It) => "coperto",
+        (Overcast, _, En) => "overcast",
+        (HighClouds, _, De) => "hohe Schleierwolken",
+        (HighClouds, _, Fr) => "voile de nuages élevés",
+        (HighClouds, _, It) => "velature in quota",
+        (HighClouds, _, En) => "high clouds",
+        (LowStratus, _, De) => "Hochnebel",
+        (LowStratus, _, Fr) => "stratus",
+        (LowStratus, _, It) => "nebbia alta",
+        (LowStratus, _, En) => "low stratus",
+        (Fog, _, De) => "Nebel",
+        (Fog, _, Fr) => "brouillard",
+        (Fog, _, It) => "nebbia",
+        (Fog, _, En) => "fog",
+    }
+}
+
+fn precipitation_description(
+    precipitation: Precipitation,
+    intensity: Option<Intensity>,
+    showers: bool,
+    lang: Language,
+) -> String {
+    use Language::*;
+    use Precipitation::*;
+    // light, moderate and heavy
+    let forms: [&str; 3] = match (precipitation, showers, lang) {
+        (Rain, false, De) => ["leichter Regen", "Regen", "starker Regen"],
+        (Rain, true, De) => [
+            "leichte Regenschauer",
+            "Regenschauer",
+            "starke Regenschauer",
+        ],
+        (Drizzle, _, De) => ["leichter Nieselregen", "Nieselregen", "starker Nieselregen"],
+        (Sleet, false, De) => ["leichter Schneeregen", "Schneeregen", "starker Schneeregen"],
+        (Sleet, true, De) => [
+            "leichte Schneeregenschauer",
+            "Schneeregenschauer",
+            "starke Schneeregenschauer",
+        ],
+        (Snow, false, De) => ["leichter Schneefall", "Schneefall", "starker Schneefall"],
+        (Snow, true, De) => [
+            "leichte Schneeschauer",
+            "Schneeschauer",
+            "starke Schneeschauer",
+        ],
+        (Hail, _, De) => ["leichter Hagel", "Hagel", "starker Hagel"],
+        (Rain, false, Fr) => ["pluie faible", "pluie", "forte pluie"],
+        (Rain, true, Fr) => [
+            "faibles averses de pluie",
+            "averses de pluie",
+            "fortes averses de pluie",
+        ],
+        (Drizzle, _, Fr) => ["bruine légère", "bruine", "forte bruine"],
+        (Sleet, false, Fr) => [
+            "faible pluie et neige mêlées",
+            "pluie et neige mêlées",
+            "forte pluie et neige mêlées",
+        ],
+        (Sleet, true, Fr) => [
+            "faibles averses de neige mouillée",
+            "averses de neige mouillée",
+            "fortes averses de neige mouillée",
+        ],
+        (Snow, false, Fr) => [
+            "faibles chutes de neige",
+            "chutes de neige",
+            "fortes chutes de neige",
+        ],
+        (Snow, true, Fr) => [
+            "faibles averses de neige",
+            "averses de neige",
+            "fortes averses de neige",
+        ],
+        (Hail, _, Fr) => ["grêle faible", "grêle", "forte grêle"],
+        (Rain, false, It) => ["pioggia debole", "pioggia", "pioggia forte"],
+        (Rain, true, It) => [
+            "deboli rovesci di pioggia",
+            "rovesci di pioggia",
+            "forti rovesci di pioggia",
+        ],
+        (Drizzle, _, It) => ["pioviggine debole", "pioviggine", "pioviggine forte"],
+        (Sleet, false, It) => [
+            "pioggia mista a neve debole",
+            "pioggia mista a neve",
+            "pioggia mista a neve forte",
+        ],
+        (Sleet, true, It) => [
+            "deboli rovesci misti a neve",
+            "rovesci misti a neve",
+            "forti rovesci misti a neve",
+        ],
+        (Snow, false, It) => ["nevicate deboli", "nevicate", "nevicate forti"],
+        (Snow, true, It) => [
+            "deboli rovesci di neve",
+            "rovesci di neve",
+            "forti rovesci di neve",
+        ],
+        (Hail, _, It) => ["grandine debole", "grandine", "grandine forte"],
+        (Rain, false, En) => ["light rain", "rain", "heavy rain"],
+        (Rain, true, En) => ["light rain showers", "rain showers", "heavy rain showers"],
+        (Drizzle, _, En) => ["light drizzle", "drizzle", "heavy drizzle"],
+        (Sleet, false, En) => ["light sleet", "sleet", "heavy sleet"],
+        (Sleet, true, En) => [
+            "light sleet showers",
+            "sleet showers",
+            "heavy sleet showers",
+        ],
+        (Snow, false, En) => ["light snow", "snow", "heavy snow"],
+        (Snow, true, En) => ["light snow showers", "snow showers", "heavy snow showers"],
+        (Hail, _, En) => ["light hail", "hail", "heavy hail"],
+    };
+    let form = match intensity {
+        Some(Intensity::Light) => forms[0],
+        Some(Intensity::Moderate) | None => forms[1],
+        Some(Intensity::Heavy) => forms[2],
+    };
+    form.to_string()
+}
+
+fn thunder_description(precipitation: Option<String>, lang: Language) -> String {
+    match (precipitation, lang) {
+        (None, Language::De) => "Gewitter".to_string(),
+        (None, Language::Fr) => "orages".to_string(),
+        (None, Language::It) => "temporali".to_string(),
+        (None, Language::En) => "thunderstorms".to_string(),
+        // all german precipitation nouns are masculine, so only the adjective changes in dative
+        (Some(p), Language::De) => format!(
+            "Gewitter mit {}",
+            p.replace("leichter ", "leichtem ")
+                .replace("starker ", "starkem ")
+        ),
+        (Some(p), Language::Fr) => format!("orages avec {}", p),
+        (Some(p), Language::It) => format!("temporali con {}", p),
+        (Some(p), Language::En) => format!("thunderstorms with {}", p),
+    }
+}
@@ -0,0 +1,149 @@
+/*
+ * Meteoschweiz: Render meteo data from meteoschweiz.admin.ch
+ * Copyright (C) 2020  Tibor Schneider
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::symbol::{CloudCover, Intensity, Precipitation, WeatherSymbol};
+use crate::{Error, Result};
+
+use config::{Config, File};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Name of the default theme, with the icons of meteoschweiz.admin.ch directly in the icon folder
+pub const DEFAULT_THEME: &str = "default";
+/// Name of the bundled monochrome theme, for e-ink displays
+pub const MONO_THEME: &str = "mono";
+/// Name of the unicode theme, for text outputs
+pub const EMOJI_THEME: &str = "emoji";
+/// File in the folder of an image theme, mapping weather symbols to file names
+const MAPPING_FILE: &str = "theme.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThemeKind {
+    /// Every weather symbol is a pdf (and png) file in the theme folder
+    Image,
+    /// Every weather symbol is a unicode character
+    Text,
+}
+
+/// Icon theme, mapping weather symbol IDs to an icon
+#[derive(Debug, Serialize)]
+pub struct Theme {
+    pub name: String,
+    pub kind: ThemeKind,
+    /// folder with the images, empty for text themes
+    pub folder: String,
+    #[serde(skip)]
+    mapping: BTreeMap<u64, String>,
+}
+
+impl Theme {
+    /// Load the theme with the given name. The default theme lives directly in the icon folder,
+    /// all other image themes in a subfolder with the name of the theme.
+    pub fn load(icon_path: &str, name: &str) -> Result<Self> {
+        if name == EMOJI_THEME {
+            return Ok(Self {
+                name: name.to_string(),
+                kind: ThemeKind::Text,
+                folder: String::new(),
+                mapping: BTreeMap::new(),
+            });
+        }
+        let mut folder = PathBuf::from(icon_path);
+        if name != DEFAULT_THEME {
+            folder.push(name);
+            if name != MONO_THEME && !folder.is_dir() {
+                return Err(Error::IconError(format!(
+                    "Unknown icon theme \"{}\": {} does not exist",
+                    name,
+                    folder.display()
+                )));
+            }
+        }
+        let mapping = read_mapping(&folder)?;
+        Ok(Self {
+            name: name.to_string(),
+            kind: ThemeKind::Image,
+            folder: folder.into_os_string().into_string().unwrap(),
+            mapping,
+        })
+    }
+
+    /// File name (without extension) of the image of the weather symbol
+    pub fn file_stem(&self, id: u64) -> String {
+        match self.mapping.get(&id) {
+            Some(stem) => stem.clone(),
+            None => id.to_string(),
+        }
+    }
+
+    /// Icon of the weather symbol, as used in the templates: the path to the pdf for image
+    /// themes, and the character for text themes.
+    pub fn icon(&self, symbol: &WeatherSymbol) -> String {
+        match self.kind {
+            ThemeKind::Image => format!("{}/{}.pdf", self.folder, self.file_stem(symbol.id)),
+            ThemeKind::Text => emoji(symbol).to_string(),
+        }
+    }
+}
+
+/// Read the optional mapping file of the theme, like `101 = "moon"`.
+fn read_mapping(folder: &Path) -> Result<BTreeMap<u64, String>> {
+    let p = folder.join(MAPPING_FILE);
+    if !p.is_file() {
+        return Ok(BTreeMap::new());
+    }
+    let mut config = Config::default();
+    let raw: BTreeMap<String, String> = config
+        .merge(File::from(p.as_path()))
+        .and_then(|c| c.clone().try_into())
+        .map_err(|e| Error::IconError(format!("Invalid {}: {}", p.display(), e)))?;
+    raw.into_iter()
+        .map(|(id, stem)| match id.parse() {
+            Ok(id) => Ok((id, stem)),
+            Err(_) => Err(Error::IconError(format!(
+                "Invalid weather symbol \"{}\" in {}",
+                id,
+                p.display()
+            ))),
+        })
+        .collect()
+}
+
+fn emoji(symbol: &WeatherSymbol) -> &'static str {
+    use CloudCover::*;
+    use Precipitation::*;
+    let sun_visible = !symbol.night && symbol.cloud_cover == PartlyCloudy;
+    match symbol.precipitation {
+        _ if symbol.thunder => "⛈️",
+        Some(Snow) if symbol.intensity == Some(Intensity::Heavy) => "❄️",
+        Some(Snow) | Some(Sleet) => "🌨️",
+        Some(Rain) | Some(Drizzle) if sun_visible => "🌦️",
+        Some(_) => "🌧️",
+        None => match (symbol.cloud_cover, symbol.night) {
+            (Clear, true) | (MostlyClear, true) | (HighClouds, true) => "🌙",
+            (Clear, false) => "☀️",
+            (MostlyClear, false) | (HighClouds, false) => "🌤️",
+            (PartlyCloudy, false) => "⛅",
+            (MostlyCloudy, false) => "🌥️",
+            (Fog, _) => "🌫️",
+            _ => "☁️",
+        },
+    }
+}
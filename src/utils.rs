@@ -17,53 +17,67 @@
  */
 
 use crate::Result;
-use std::env;
 use std::fs;
-use std::path::PathBuf;
-use std::process::Command;
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
 
-static NUM_ICONS: usize = 35;
-static ICON_URL_BASE: &str = "https://www.meteoschweiz.admin.ch/etc/designs/meteoswiss/assets/images/icons/meteo/weather-symbols/";
+/// 64-bit FNV-1a hash. Unlike the `DefaultHasher`, the result is stable across builds, such that
+/// it can be stored in the cache.
+pub struct Fnv64(u64);
 
-pub fn fetch_icons(icon_folder: &str) -> Result<()> {
-    // export the old current path
-    let old_path = env::current_dir()?;
-    // check if icon folder already exists
-    let mut p = PathBuf::from(icon_folder);
-    // if it is not a directory, recursively generate it
-    if !p.is_dir() {
-        fs::create_dir_all(&p)?;
+impl Default for Fnv64 {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
     }
-    // change directory
-    env::set_current_dir(&p)?;
+}
 
-    // loop over all
-    for i in 0..NUM_ICONS {
-        let icon_idx = i + 1;
-        p.push(format!("{}.pdf", icon_idx));
-        if !p.is_file() {
-            println!("Downloading and converting: {:?}", p);
-            // downoad the image
-            Command::new("wget")
-                .arg(format!("{}{}.svg", ICON_URL_BASE, icon_idx))
-                .output()?;
+impl Hasher for Fnv64 {
+    fn finish(&self) -> u64 {
+        self.0
+    }
 
-            // convert the image to a pdf
-            Command::new("inkscape")
-                .arg(format!("{}.svg", icon_idx))
-                .arg(format!("--export-filename={}.pdf", icon_idx))
-                .arg("--export-type=pdf")
-                .output()?;
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
         }
-        p.pop();
     }
+}
 
-    // change directory back
-    env::set_current_dir(old_path)?;
-
+/// Write the file by first writing to a temporary file, and renaming it afterwards. Readers
+/// therefore either see the old or the new content, but never a half-written file.
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".tmp{}", std::process::id()));
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path)?;
     Ok(())
 }
 
+/// Format a duration in seconds, like `2d 3h` or `5h 12min`
+pub fn format_age(seconds: i64) -> String {
+    let minutes = seconds.max(0) / 60;
+    let (days, hours, minutes) = (minutes / 1440, (minutes / 60) % 24, minutes % 60);
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}min", hours, minutes)
+    } else {
+        format!("{}min", minutes)
+    }
+}
+
+/// Format a file size in bytes
+pub fn format_size(bytes: u64) -> String {
+    if bytes < 1024 {
+        format!("{} B", bytes)
+    } else if bytes < 1024 * 1024 {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
 pub fn generate_template(template_file: &str, template_long_file: &str) -> Result<()> {
     let p_short = PathBuf::from(template_file);
     // check if the template file already exists
@@ -0,0 +1,320 @@
+/*
+ * Meteoschweiz: Render meteo data from meteoschweiz.admin.ch
+ * Copyright (C) 2020  Tibor Schneider
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::symbol::{Descriptions, Language};
+use crate::{Error, Result};
+
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+/// Direction the wind comes from, as one of the 16 points of the compass.
+///
+/// The cache only stores the index, templates get the `name` (like `NNE`) and the `degrees`
+/// (clockwise from north).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "u8")]
+pub enum Compass {
+    N,
+    Nne,
+    Ne,
+    Ene,
+    E,
+    Ese,
+    Se,
+    Sse,
+    S,
+    Ssw,
+    Sw,
+    Wsw,
+    W,
+    Wnw,
+    Nw,
+    Nnw,
+}
+
+const POINTS: [Compass; 16] = {
+    use Compass::*;
+    [
+        N, Nne, Ne, Ene, E, Ese, Se, Sse, S, Ssw, Sw, Wsw, W, Wnw, Nw, Nnw,
+    ]
+};
+
+const NAMES: [&str; 16] = [
+    "N", "NNE", "NE", "ENE", "E", "ESE", "SE", "SSE", "S", "SSW", "SW", "WSW", "W", "WNW", "NW",
+    "NNW",
+];
+
+impl Compass {
+    pub fn name(self) -> &'static str {
+        NAMES[self as usize]
+    }
+
+    pub fn degrees(self) -> f64 {
+        self as usize as f64 * 22.5
+    }
+}
+
+impl FromStr for Compass {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        NAMES
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(s.trim()))
+            .map(|i| POINTS[i])
+            .ok_or(Error::ForecastBuildError("Unknown wind direction"))
+    }
+}
+
+impl TryFrom<u8> for Compass {
+    type Error = String;
+
+    fn try_from(i: u8) -> std::result::Result<Self, String> {
+        POINTS
+            .get(i as usize)
+            .copied()
+            .ok_or_else(|| format!("Invalid wind direction {}", i))
+    }
+}
+
+impl Serialize for Compass {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return serializer.serialize_u8(*self as u8);
+        }
+        let mut s = serializer.serialize_struct("Compass", 2)?;
+        s.serialize_field("name", self.name())?;
+        s.serialize_field("degrees", &self.degrees())?;
+        s.end()
+    }
+}
+
+/// Upper bounds of the Beaufort classes 0 to 11, in km/h
+const BEAUFORT_LIMITS: [f64; 12] = [
+    1.0, 6.0, 12.0, 20.0, 29.0, 39.0, 50.0, 62.0, 75.0, 89.0, 103.0, 118.0,
+];
+
+/// Beaufort class (0 to 12) of the wind speed.
+///
+/// The cache only stores the class, templates also get the description in every language.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(from = "u8")]
+pub struct Beaufort(u8);
+
+impl Beaufort {
+    /// Beaufort class of the wind speed in km/h
+    pub fn from_kmh(speed: f64) -> Self {
+        Self(
+            BEAUFORT_LIMITS
+                .iter()
+                .take_while(|limit| speed >= **limit)
+                .count() as u8,
+        )
+    }
+
+    pub fn class(self) -> u8 {
+        self.0
+    }
+
+    /// Name of the class, like "gentle breeze"
+    pub fn description(self, lang: Language) -> &'static str {
+        use Language::*;
+        let names = match lang {
+            De => [
+                "Windstille",
+                "leiser Zug",
+                "leichte Brise",
+                "schwache Brise",
+                "mässige Brise",
+                "frische Brise",
+                "starker Wind",
+                "steifer Wind",
+                "stürmischer Wind",
+                "Sturm",
+                "schwerer Sturm",
+                "orkanartiger Sturm",
+                "Orkan",
+            ],
+            Fr => [
+                "calme",
+                "très légère brise",
+                "légère brise",
+                "petite brise",
+                "jolie brise",
+                "bonne brise",
+                "vent frais",
+                "grand frais",
+                "coup de vent",
+                "fort coup de vent",
+                "tempête",
+                "violente tempête",
+                "ouragan",
+            ],
+            It => [
+                "calma",
+                "bava di vento",
+                "brezza leggera",
+                "brezza tesa",
+                "vento moderato",
+                "vento teso",
+                "vento fresco",
+                "vento forte",
+                "burrasca",
+                "burrasca forte",
+                "tempesta",
+                "fortunale",
+                "uragano",
+            ],
+            En => [
+                "calm",
+                "light air",
+                "light breeze",
+                "gentle breeze",
+                "moderate breeze",
+                "fresh breeze",
+                "strong breeze",
+                "near gale",
+                "gale",
+                "strong gale",
+                "storm",
+                "violent storm",
+                "hurricane",
+            ],
+        };
+        names[self.0.min(12) as usize]
+    }
+}
+
+impl From<u8> for Beaufort {
+    fn from(class: u8) -> Self {
+        Self(class.min(12))
+    }
+}
+
+impl Serialize for Beaufort {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return serializer.serialize_u8(self.0);
+        }
+        let mut s = serializer.serialize_struct("Beaufort", 2)?;
+        s.serialize_field("class", &self.0)?;
+        s.serialize_field(
+            "description",
+            &Descriptions::new(|lang| self.description(lang).to_string()),
+        )?;
+        s.end()
+    }
+}
+
+/// One sector of the wind rose: how often the wind comes from this direction, and how strong
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WindRose {
+    direction: Compass,
+    /// Fraction of the samples from this direction
+    share: f64,
+    /// Mean speed of the samples from this direction, in km/h
+    mean_speed: f64,
+}
+
+/// Aggregate the wind samples (direction and speed) into all 16 sectors of the wind rose
+pub fn wind_rose<I: IntoIterator<Item = (Compass, f64)>>(samples: I) -> Vec<WindRose> {
+    let mut count = [0usize; 16];
+    let mut total = [0.0; 16];
+    for (direction, speed) in samples {
+        count[direction as usize] += 1;
+        total[direction as usize] += speed;
+    }
+    let n = count.iter().sum::<usize>().max(1) as f64;
+    POINTS
+        .iter()
+        .map(|direction| {
+            let i = *direction as usize;
+            WindRose {
+                direction: *direction,
+                share: count[i] as f64 / n,
+                mean_speed: if count[i] > 0 {
+                    total[i] / count[i] as f64
+                } else {
+                    0.0
+                },
+            }
+        })
+        .collect()
+}
+
+/// Short summary of the wind, like "NE 3 Bft, gusts 45 km/h". The direction is the most frequent
+/// one in the wind rose, the Beaufort class is the one of the strongest sample.
+pub fn summary(rose: &[WindRose], strongest: Beaufort, gusts: Option<f64>) -> String {
+    let direction = rose
+        .iter()
+        .filter(|sector| sector.share > 0.0)
+        .max_by(|a, b| {
+            a.share
+                .partial_cmp(&b.share)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+    let mut text = match direction {
+        Some(sector) => format!("{} {} Bft", sector.direction.name(), strongest.class()),
+        None => format!("{} Bft", strongest.class()),
+    };
+    if let Some(gusts) = gusts {
+        text.push_str(&format!(", gusts {:.0} km/h", gusts));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compass_from_str() {
+        assert_eq!("N".parse::<Compass>().unwrap(), Compass::N);
+        assert_eq!("nne".parse::<Compass>().unwrap(), Compass::Nne);
+        assert_eq!(" SW ".parse::<Compass>().unwrap(), Compass::Sw);
+        assert!("NNNE".parse::<Compass>().is_err());
+        assert!("".parse::<Compass>().is_err());
+        for (point, name) in POINTS.iter().zip(NAMES.iter()) {
+            assert_eq!(name.parse::<Compass>().unwrap(), *point);
+        }
+    }
+
+    #[test]
+    fn compass_degrees() {
+        assert_eq!(Compass::N.degrees(), 0.0);
+        assert_eq!(Compass::Ene.degrees(), 67.5);
+        assert_eq!(Compass::Nnw.degrees(), 337.5);
+    }
+
+    #[test]
+    fn beaufort_from_kmh() {
+        let class = |kmh| Beaufort::from_kmh(kmh).class();
+        assert_eq!(class(0.0), 0);
+        assert_eq!(class(0.9), 0);
+        assert_eq!(class(1.0), 1);
+        assert_eq!(class(5.9), 1);
+        assert_eq!(class(6.0), 2);
+        assert_eq!(class(28.9), 4);
+        assert_eq!(class(29.0), 5);
+        assert_eq!(class(117.9), 11);
+        assert_eq!(class(118.0), 12);
+        assert_eq!(class(250.0), 12);
+    }
+}